@@ -0,0 +1,350 @@
+use {
+    crate::{
+        error::Error,
+        executor::{
+            Executor,
+            LocalExecutor,
+            Output,
+            Process,
+            ResolvedTask,
+            Spawn,
+            Termination,
+            Terminator,
+            TERMINATION_GRACE,
+        },
+    },
+    anyhow::Result,
+    std::{
+        io::{
+            BufRead,
+            BufReader,
+            Read,
+            Write,
+        },
+        net::{
+            Shutdown,
+            SocketAddr,
+            TcpListener,
+            TcpStream,
+        },
+        sync::{
+            atomic::{
+                AtomicUsize,
+                Ordering,
+            },
+            Arc,
+            Mutex,
+        },
+    },
+};
+
+/// The maximum size of a request in bytes. Larger requests are rejected
+/// before they are parsed.
+pub const MAX_REQUEST: u64 = 16 * 1024 * 1024;
+
+/// Sent by the coordinator as the only line on a connection.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub struct Request {
+    /// The token shared by the coordinator and the agent.
+    pub token: String,
+    pub task: ResolvedTask,
+    pub stdout: bool,
+    pub stderr: bool,
+}
+
+/// Sent by the agent, one per line, until the task exited.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Response {
+    Stdout(String),
+    Stderr(String),
+    Exit(Option<i32>),
+    Error(String),
+}
+
+fn send(stream: &Mutex<TcpStream>, response: &Response) -> Result<()> {
+    let mut line = serde_json::to_string(response)?;
+    line.push('\n');
+    stream.lock().unwrap().write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Compares the tokens in constant time (for tokens of the same length) so
+/// that the token can not be guessed from the response times.
+fn token_matches(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Runs the tasks it receives with the [`LocalExecutor`], relative to its own
+/// working directory. Every connection carries a single task, closing the
+/// connection terminates the task. Tasks are only accepted with the token the
+/// agent was bound with.
+pub struct Agent {
+    listener: TcpListener,
+    token: Arc<String>,
+}
+
+impl Agent {
+    pub fn bind(addr: &str, token: &str) -> Result<Self> {
+        if token.is_empty() {
+            Err(Error::Argument("the agent token must not be empty".to_owned()))?
+        }
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+            token: Arc::new(token.to_owned()),
+        })
+    }
+
+    /// The address the agent listens on.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accepts connections until the listener fails, running every task in its
+    /// own thread.
+    pub fn serve(&self) -> Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            let token = self.token.clone();
+            std::thread::spawn(move || {
+                let peer = stream.peer_addr().map(|v| v.to_string()).unwrap_or_default();
+                if let Err(e) = Self::handle(stream, &token) {
+                    eprintln!("{}: {}", peer, e);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    fn handle(stream: TcpStream, token: &str) -> Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let writer = Arc::new(Mutex::new(stream));
+
+        // the request is read before the token is checked, so its size is capped
+        let mut line = String::new();
+        (&mut reader).take(MAX_REQUEST).read_line(&mut line)?;
+        if !line.ends_with('\n') {
+            send(&writer, &Response::Error("incomplete or oversized request".to_owned()))?;
+            Err(Error::Argument(format!(
+                "rejected a request without a newline within {} bytes",
+                MAX_REQUEST
+            )))?
+        }
+        let request = match serde_json::from_str::<Request>(&line) {
+            | Ok(v) if token_matches(&v.token, token) => v,
+            | _ => {
+                send(&writer, &Response::Error("unauthorized".to_owned()))?;
+                Err(Error::Argument("rejected a request without a valid token".to_owned()))?
+            },
+        };
+        let output = |v: bool| {
+            match v {
+                | true => Output::Piped,
+                | false => Output::Null,
+            }
+        };
+        let mut process = match LocalExecutor.spawn(&request.task, Spawn {
+            stdout: output(request.stdout),
+            stderr: output(request.stderr),
+            terminable: true,
+        }) {
+            | Ok(v) => v,
+            | Err(e) => return send(&writer, &Response::Error(e.to_string())),
+        };
+
+        // the coordinator closes the connection to terminate the task
+        let terminate = process.terminator();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            while let Ok(n) = reader.read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+            }
+            terminate(Termination::Graceful);
+            std::thread::sleep(TERMINATION_GRACE);
+            terminate(Termination::Forced);
+        });
+
+        let forward = |from: Option<Box<dyn Read+Send>>, response: fn(String) -> Response| {
+            let writer = writer.clone();
+            from.map(|from| {
+                std::thread::spawn(move || -> Result<()> {
+                    let mut reader = BufReader::new(from);
+                    let mut buf = Vec::<u8>::new();
+                    loop {
+                        buf.clear();
+                        if reader.read_until(b'\n', &mut buf)? == 0 {
+                            return Ok(());
+                        }
+                        send(&writer, &response(String::from_utf8_lossy(&buf).into_owned()))?;
+                    }
+                })
+            })
+        };
+        let forwards = [
+            forward(process.stdout(), Response::Stdout),
+            forward(process.stderr(), Response::Stderr),
+        ];
+        let status = process.wait();
+        for f in forwards.into_iter().flatten() {
+            f.join().expect("output forwarding panicked")?;
+        }
+        match status {
+            | Ok(code) => send(&writer, &Response::Exit(code)),
+            | Err(e) => send(&writer, &Response::Error(e.to_string())),
+        }
+    }
+}
+
+/// Runs tasks on agents, distributing them round robin.
+pub struct RemoteExecutor {
+    agents: Vec<String>,
+    /// The token the agents were started with.
+    token: String,
+    next: AtomicUsize,
+}
+
+impl RemoteExecutor {
+    pub fn new(agents: Vec<String>, token: &str) -> Result<Self> {
+        if agents.is_empty() {
+            Err(Error::Argument("no agents given".to_owned()))?
+        }
+        Ok(Self {
+            agents,
+            token: token.to_owned(),
+            next: AtomicUsize::new(0),
+        })
+    }
+}
+
+impl Executor for RemoteExecutor {
+    fn is_remote(&self) -> bool {
+        true
+    }
+
+    fn spawn(&self, task: &ResolvedTask, spawn: Spawn) -> Result<Box<dyn Process>> {
+        if task.interactive {
+            Err(Error::InvalidTask(
+                "interactive scripts can not run on agents".to_owned(),
+            ))?
+        }
+        let agent = &self.agents[self.next.fetch_add(1, Ordering::Relaxed) % self.agents.len()];
+        let mut stream = match TcpStream::connect(agent) {
+            | Ok(v) => v,
+            | Err(e) => Err(Error::ChildProcess(format!("agent {}: {}", agent, e)))?,
+        };
+        let mut line = serde_json::to_string(&Request {
+            token: self.token.clone(),
+            task: task.clone(),
+            stdout: spawn.stdout != Output::Null,
+            stderr: spawn.stderr != Output::Null,
+        })?;
+        line.push('\n');
+        stream.write_all(line.as_bytes())?;
+
+        let (stdout_tx, stdout_rx) = flume::unbounded::<Vec<u8>>();
+        let (stderr_tx, stderr_rx) = flume::unbounded::<Vec<u8>>();
+        let (exit_tx, exit_rx) = flume::bounded::<Result<Option<i32>>>(1);
+        let reader = BufReader::new(stream.try_clone()?);
+        let name = agent.clone();
+        std::thread::spawn(move || {
+            let exit = || -> Result<Option<i32>> {
+                for line in reader.lines() {
+                    match serde_json::from_str::<Response>(&line?)? {
+                        | Response::Stdout(v) => {
+                            let _ = stdout_tx.send(v.into_bytes());
+                        },
+                        | Response::Stderr(v) => {
+                            let _ = stderr_tx.send(v.into_bytes());
+                        },
+                        | Response::Exit(code) => return Ok(code),
+                        | Response::Error(e) => Err(Error::ChildProcess(format!("agent {}: {}", name, e)))?,
+                    }
+                }
+                Err(Error::ChildProcess(format!("agent {} closed the connection", name)).into())
+            }();
+            drop((stdout_tx, stderr_tx));
+            let _ = exit_tx.send(exit);
+        });
+
+        Ok(Box::new(RemoteProcess {
+            stream,
+            stdout: Some(stdout_rx),
+            stderr: Some(stderr_rx),
+            exit: exit_rx,
+        }))
+    }
+}
+
+/// A task running on an agent.
+struct RemoteProcess {
+    stream: TcpStream,
+    stdout: Option<flume::Receiver<Vec<u8>>>,
+    stderr: Option<flume::Receiver<Vec<u8>>>,
+    exit: flume::Receiver<Result<Option<i32>>>,
+}
+
+impl Process for RemoteProcess {
+    fn stdout(&mut self) -> Option<Box<dyn Read+Send>> {
+        self.stdout
+            .take()
+            .map(|v| Box::new(ChannelReader::new(v)) as Box<dyn Read+Send>)
+    }
+
+    fn stderr(&mut self) -> Option<Box<dyn Read+Send>> {
+        self.stderr
+            .take()
+            .map(|v| Box::new(ChannelReader::new(v)) as Box<dyn Read+Send>)
+    }
+
+    fn wait(&mut self) -> Result<Option<i32>> {
+        self.exit.recv()?
+    }
+
+    fn terminator(&self) -> Terminator {
+        let stream = self.stream.try_clone();
+        // the agent terminates the task once the connection is closed
+        Box::new(move |_| {
+            if let Ok(s) = &stream {
+                let _ = s.shutdown(Shutdown::Both);
+            }
+        })
+    }
+}
+
+/// Reads the chunks received through a channel until all senders are gone.
+struct ChannelReader {
+    rx: flume::Receiver<Vec<u8>>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl ChannelReader {
+    fn new(rx: flume::Receiver<Vec<u8>>) -> Self {
+        Self {
+            rx,
+            buf: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos >= self.buf.len() {
+            match self.rx.recv() {
+                | Ok(v) => {
+                    self.buf = v;
+                    self.pos = 0;
+                },
+                | Err(_) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.buf.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
@@ -1,15 +1,19 @@
 use {
-    crate::{
-        error::Error,
-        plan::ExecutionPlan,
-        workflow::Workflow,
-    },
     anyhow::Result,
     clap::{
         Arg,
         ArgAction,
     },
     itertools::Itertools,
+    neomake::{
+        error::Error,
+        format::Format,
+        plan::ExecutionPlan,
+        workflow::{
+            Resources,
+            Workflow,
+        },
+    },
     std::{
         collections::{
             HashMap,
@@ -21,6 +25,17 @@ use {
     },
 };
 
+/// Errors of the command line that are not raised by the library.
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum CliError {
+    /// The command requires the experimental flag.
+    #[error("experimental command: {0}")]
+    ExperimentalCommand(String),
+    /// The command is not known.
+    #[error("unknown command")]
+    UnknownCommand,
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub(crate) enum Privilege {
     Normal,
@@ -40,8 +55,9 @@ impl CallArgs {
         }
 
         match &self.command {
-            | Command::Multiplex { .. } => Err(Error::ExperimentalCommand("multiplex".to_owned()))?,
-            | Command::Watch { .. } => Err(Error::ExperimentalCommand("watch".to_owned()))?,
+            | Command::Multiplex { .. } => Err(CliError::ExperimentalCommand("multiplex".to_owned()))?,
+            | Command::Watch { .. } => Err(CliError::ExperimentalCommand("watch".to_owned()))?,
+            | Command::Agent { .. } => Err(CliError::ExperimentalCommand("agent".to_owned()))?,
             | _ => (),
         }
 
@@ -55,80 +71,12 @@ pub(crate) enum ManualFormat {
     Markdown,
 }
 
-#[derive(Debug)]
-pub(crate) enum Format {
-    YAML,
-    #[cfg(feature = "format+json")]
-    JSON {
-        pretty: bool,
-    },
-    #[cfg(feature = "format+toml")]
-    TOML,
-    #[cfg(feature = "format+ron")]
-    RON {
-        pretty: bool,
-    },
-}
-
-impl Format {
-    pub(crate) fn serialize<T: serde::Serialize>(&self, arg: &T) -> Result<String> {
-        match self {
-            | crate::args::Format::YAML => Ok(serde_yaml::to_string(arg)?),
-            #[cfg(feature = "format+json")]
-            | crate::args::Format::JSON { pretty } => {
-                if *pretty {
-                    Ok(serde_json::to_string_pretty(arg)?)
-                } else {
-                    Ok(serde_json::to_string(arg)?)
-                }
-            },
-            #[cfg(feature = "format+toml")]
-            | crate::args::Format::TOML => Ok(toml::to_string(arg)?),
-            #[cfg(feature = "format+ron")]
-            | crate::args::Format::RON { pretty } => {
-                if *pretty {
-                    Ok(ron::ser::to_string_pretty(
-                        arg,
-                        ron::ser::PrettyConfig::new()
-                            .compact_arrays(true)
-                            .enumerate_arrays(true)
-                            .new_line("\n".to_owned()), // no windows on my turf
-                    )?)
-                } else {
-                    Ok(ron::ser::to_string(arg)?)
-                }
-            },
-        }
-    }
-
-    pub(crate) fn deserialize<T: serde::de::DeserializeOwned>(&self, s: &str) -> Result<T> {
-        match self {
-            | crate::args::Format::YAML => Ok(serde_yaml::from_str::<T>(s)?),
-            #[cfg(feature = "format+json")]
-            | crate::args::Format::JSON { .. } => Ok(serde_json::from_str::<T>(s)?),
-            #[cfg(feature = "format+toml")]
-            | crate::args::Format::TOML => Ok(toml::from_str::<T>(s)?),
-            #[cfg(feature = "format+ron")]
-            | crate::args::Format::RON { .. } => Ok(ron::from_str::<T>(s)?),
-        }
-    }
-
-    fn from_arg(arg: &str) -> Result<Self> {
-        match arg {
-            | "yaml" => Ok(Format::YAML),
-            #[cfg(feature = "format+json")]
-            | "json" => Ok(Format::JSON { pretty: false }),
-            #[cfg(feature = "format+json")]
-            | "json+p" => Ok(Format::JSON { pretty: true }),
-            #[cfg(feature = "format+toml")]
-            | "toml" => Ok(Format::TOML),
-            #[cfg(feature = "format+ron")]
-            | "ron" => Ok(Format::RON { pretty: false }),
-            #[cfg(feature = "format+ron")]
-            | "ron+p" => Ok(Format::RON { pretty: true }),
-            | _ => Err(Error::Argument("output".to_owned()).into()),
-        }
-    }
+/// When to restart multiplexed commands after they exited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RestartPolicy {
+    Never,
+    OnFailure,
+    Always,
 }
 
 #[derive(Debug)]
@@ -194,15 +142,37 @@ pub(crate) enum Command {
     },
     WorkflowSchema,
     Execute {
-        plan: ExecutionPlan,
+        plan: Box<ExecutionPlan>,
         workers: usize,
+        secrets: HashMap<String, String>,
+        history: Option<String>,
         no_stdout: bool,
         no_stderr: bool,
+        /// The memory capacity in bytes.
+        memory: Option<u64>,
+        /// Capacities of named resources, replacing the ones of the plan.
+        resources: HashMap<String, u64>,
+        /// Where the lock files are created.
+        lock_dir: Option<String>,
+        /// Where the output of the scripts is logged.
+        log_dir: Option<String>,
+        /// Whether logged output is forwarded as well.
+        tee: bool,
+        /// Addresses of the agents to run the script tasks on.
+        agents: Vec<String>,
+        /// The token of the agents, empty if no agents are given.
+        agent_token: String,
+    },
+    Agent {
+        listen: String,
+        token: String,
+        allow_remote: bool,
     },
     Plan {
         workflow: String,
         nodes: Nodes,
         args: HashMap<String, String>,
+        secret_args: HashSet<String>,
         format: Format,
     },
     List {
@@ -216,13 +186,46 @@ pub(crate) enum Command {
     },
     Watch {
         workflow: String,
-        watch: String,
+        /// The watches to run. All watches if not set.
+        watches: Option<Vec<String>>,
         args: HashMap<String, String>,
+        secret_args: HashSet<String>,
+        secrets: HashMap<String, String>,
+        history: Option<String>,
         workers: usize,
         root: String,
+        /// Where the lock files are created.
+        lock_dir: Option<String>,
+        /// Polling interval in milliseconds, switching all watches to the
+        /// polling backend.
+        poll: Option<u64>,
     },
     Multiplex {
         commands: Vec<String>,
+        /// The workflow, only loaded if nodes are selected.
+        workflow: Option<String>,
+        nodes: HashSet<String>,
+        args: HashMap<String, String>,
+        secret_args: HashSet<String>,
+        secrets: HashMap<String, String>,
+        workers: usize,
+        history: Option<String>,
+        restart: RestartPolicy,
+        exit_on_first_failure: bool,
+    },
+    HistoryList {
+        dir: String,
+        limit: Option<usize>,
+        format: Format,
+    },
+    HistoryShow {
+        dir: String,
+        id: String,
+        format: Format,
+    },
+    HistoryStats {
+        dir: String,
+        format: Format,
     },
 }
 
@@ -286,8 +289,39 @@ impl ClapArgumentLoader {
                             .help("The workflow file to use.")
                             .default_value("./.neomake.yaml"),
                     )
-                    .arg(clap::Arg::new("watch").short('w').long("watch").required(true))
-                    .arg(clap::Arg::new("root").short('r').long("root").default_value("./"))
+                    .arg(
+                        clap::Arg::new("watch")
+                            .short('w')
+                            .long("watch")
+                            .action(ArgAction::Append)
+                            .conflicts_with("all")
+                            .required_unless_present("all")
+                            .help("Adding a watch to run."),
+                    )
+                    .arg(
+                        clap::Arg::new("all")
+                            .long("all")
+                            .conflicts_with("watch")
+                            .required_unless_present("watch")
+                            .help("Runs all watches.")
+                            .num_args(0),
+                    )
+                    .arg(
+                        clap::Arg::new("root")
+                            .short('r')
+                            .long("root")
+                            .help("The root that the paths of the watches and events are relative to.")
+                            .default_value("./"),
+                    )
+                    .arg(
+                        Arg::new("lock-dir").long("lock-dir").help(
+                            "Enforces the node locks across processes through lock files in the given directory.",
+                        ),
+                    )
+                    .arg(clap::Arg::new("poll").long("poll").help(
+                        "Polls the filesystem in the given interval (milliseconds) instead of relying on native \
+                         events.",
+                    ))
                     .arg(
                         Arg::new("arg")
                             .short('a')
@@ -295,11 +329,38 @@ impl ClapArgumentLoader {
                             .action(ArgAction::Append)
                             .help("Specifies a value for handlebars placeholders."),
                     )
+                    .arg(
+                        Arg::new("secret-arg")
+                            .long("secret-arg")
+                            .action(ArgAction::Append)
+                            .help(
+                                "Marks a handlebars placeholder as secret. Its value is not rendered into the plan \
+                                 but resolved like a secret env var when the plan is executed.",
+                            ),
+                    )
+                    .arg(
+                        Arg::new("secret")
+                            .short('s')
+                            .long("secret")
+                            .action(ArgAction::Append)
+                            .help(
+                                "Specifies a secret env var (KEY=VALUE) that is passed to all tasks and masked in \
+                                 their output.",
+                            ),
+                    )
                     .arg(
                         Arg::new("workers")
                             .long("workers")
-                            .help("Defines how many worker threads are created in the OS thread pool.")
+                            .help(
+                                "The worker capacity, i.e. how many invocations run in parallel unless nodes declare \
+                                 cpu resources.",
+                            )
                             .default_value("1"),
+                    )
+                    .arg(
+                        Arg::new("history")
+                            .long("history")
+                            .help("Records every run in the given directory (like ./.neomake/history)."),
                     ),
             )
             .subcommand(
@@ -360,6 +421,15 @@ impl ClapArgumentLoader {
                             .action(ArgAction::Append)
                             .help("Specifies a value for handlebars placeholders."),
                     )
+                    .arg(
+                        Arg::new("secret-arg")
+                            .long("secret-arg")
+                            .action(ArgAction::Append)
+                            .help(
+                                "Marks a handlebars placeholder as secret. Its value is not rendered into the plan \
+                                 but resolved like a secret env var when the plan is executed.",
+                            ),
+                    )
                     .arg(
                         Arg::new("output")
                             .short('o')
@@ -381,13 +451,31 @@ impl ClapArgumentLoader {
                             .value_parser(input_formats.clone())
                             .default_value(*input_formats.first().unwrap()),
                     )
+                    .arg(
+                        Arg::new("secret")
+                            .short('s')
+                            .long("secret")
+                            .action(ArgAction::Append)
+                            .help(
+                                "Specifies a secret env var (KEY=VALUE) that is passed to all tasks and masked in \
+                                 their output.",
+                            ),
+                    )
                     .arg(
                         Arg::new("workers")
                             .short('w')
                             .long("workers")
-                            .help("Defines how many worker threads are created in the OS thread pool.")
+                            .help(
+                                "The worker capacity, i.e. how many invocations run in parallel unless nodes declare \
+                                 cpu resources.",
+                            )
                             .default_value("1"),
                     )
+                    .arg(
+                        Arg::new("history")
+                            .long("history")
+                            .help("Records every run in the given directory (like ./.neomake/history)."),
+                    )
                     .arg(
                         Arg::new("no-stdout")
                             .long("no-stdout")
@@ -405,6 +493,63 @@ impl ClapArgumentLoader {
                                  the logs clean.",
                             )
                             .num_args(0),
+                    )
+                    .arg(Arg::new("memory").long("memory").help(
+                        "The memory capacity (like \"16G\") that the memory resources of the running tasks must not \
+                         exceed.",
+                    ))
+                    .arg(
+                        Arg::new("resource")
+                            .long("resource")
+                            .action(ArgAction::Append)
+                            .help("Sets the capacity of a named resource (NAME=N), replacing the one of the workflow."),
+                    )
+                    .arg(
+                        Arg::new("lock-dir").long("lock-dir").help(
+                            "Enforces the node locks across processes through lock files in the given directory.",
+                        ),
+                    )
+                    .arg(Arg::new("log-dir").long("log-dir").help(
+                        "Writes the stdout and stderr of every script to <dir>/<node>/<coords>/<task-index>.log \
+                         instead of forwarding them.",
+                    ))
+                    .arg(
+                        Arg::new("tee")
+                            .long("tee")
+                            .help("Forwards the output as well when writing it to log files.")
+                            .requires("log-dir")
+                            .num_args(0),
+                    )
+                    .arg(Arg::new("agents").long("agents").help(
+                        "Runs the script tasks of nodes without an executor on the given agents (comma separated \
+                         host:port list).",
+                    ))
+                    .arg(
+                        Arg::new("agent-token").long("agent-token").requires("agents").help(
+                            "The token the agents were started with. Defaults to the NEOMAKE_AGENT_TOKEN env var.",
+                        ),
+                    ),
+            )
+            .subcommand(
+                clap::Command::new("agent")
+                    .about("Runs tasks received from other neomake processes.")
+                    .arg(
+                        Arg::new("listen")
+                            .short('l')
+                            .long("listen")
+                            .help("The address to listen on.")
+                            .default_value("127.0.0.1:7878"),
+                    )
+                    .arg(
+                        Arg::new("token").long("token").help(
+                            "The token tasks are only accepted with. Defaults to the NEOMAKE_AGENT_TOKEN env var.",
+                        ),
+                    )
+                    .arg(
+                        Arg::new("allow-remote")
+                            .long("allow-remote")
+                            .help("Allows listening on addresses other than loopback ones.")
+                            .num_args(0),
                     ),
             )
             .subcommand(
@@ -462,6 +607,43 @@ impl ClapArgumentLoader {
                             .default_value(output_formats.first().unwrap()),
                     ),
             )
+            .subcommand(
+                clap::Command::new("history")
+                    .about("Browses the recorded runs.")
+                    .subcommand_required(true)
+                    .arg(
+                        Arg::new("history")
+                            .long("history")
+                            .help("The directory in which the runs are recorded.")
+                            .global(true)
+                            .default_value("./.neomake/history"),
+                    )
+                    .arg(
+                        Arg::new("output")
+                            .short('o')
+                            .long("output")
+                            .help("The output format.")
+                            .global(true)
+                            .value_parser(output_formats.clone())
+                            .default_value(output_formats.first().unwrap()),
+                    )
+                    .subcommand(
+                        clap::Command::new("ls")
+                            .about("Lists the recorded runs, oldest first.")
+                            .arg(
+                                Arg::new("limit")
+                                    .short('l')
+                                    .long("limit")
+                                    .help("Only lists the given number of most recent runs."),
+                            ),
+                    )
+                    .subcommand(
+                        clap::Command::new("show")
+                            .about("Shows a recorded run including all of its tasks.")
+                            .arg(Arg::new("id").required(true).help("The ID of the run.")),
+                    )
+                    .subcommand(clap::Command::new("stats").about("Shows the failure rate and durations per node.")),
+            )
             .subcommand(
                 clap::Command::new("multiplex")
                     .about("Multiplex commands")
@@ -476,7 +658,71 @@ impl ClapArgumentLoader {
                     .arg(clap::Arg::new("file").short('f').long("file").help(
                         "Define a commands file. The content will be split per line, which are then interpreted as \
                          individual commands.",
-                    )),
+                    ))
+                    .arg(
+                        Arg::new("workflow")
+                            .long("workflow")
+                            .help("The workflow file to use.")
+                            .default_value("./.neomake.yaml"),
+                    )
+                    .arg(
+                        Arg::new("node")
+                            .short('n')
+                            .long("node")
+                            .action(ArgAction::Append)
+                            .help("Adding a node whose invocations are executed."),
+                    )
+                    .arg(
+                        Arg::new("restart")
+                            .long("restart")
+                            .help("Restarts commands after they exited, with an increasing delay on quick exits.")
+                            .value_parser(["never", "on-failure", "always"])
+                            .default_value("never"),
+                    )
+                    .arg(
+                        Arg::new("exit-on-first-failure")
+                            .long("exit-on-first-failure")
+                            .help("Terminates all commands and exits as soon as one command failed.")
+                            .num_args(0),
+                    )
+                    .arg(
+                        Arg::new("arg")
+                            .short('a')
+                            .long("arg")
+                            .action(ArgAction::Append)
+                            .help("Specifies a value for handlebars placeholders."),
+                    )
+                    .arg(
+                        Arg::new("secret-arg")
+                            .long("secret-arg")
+                            .action(ArgAction::Append)
+                            .help(
+                                "Marks a handlebars placeholder as secret. Its value is not rendered into the plan \
+                                 but resolved like a secret env var when the plan is executed.",
+                            ),
+                    )
+                    .arg(
+                        Arg::new("secret")
+                            .short('s')
+                            .long("secret")
+                            .action(ArgAction::Append)
+                            .help(
+                                "Specifies a secret env var (KEY=VALUE) that is passed to all tasks and masked in \
+                                 their output.",
+                            ),
+                    )
+                    .arg(
+                        Arg::new("workers")
+                            .short('w')
+                            .long("workers")
+                            .help("The worker capacity for the prerequisites of the nodes.")
+                            .default_value("1"),
+                    )
+                    .arg(
+                        Arg::new("history")
+                            .long("history")
+                            .help("Records every run in the given directory (like ./.neomake/history)."),
+                    ),
             )
     }
 
@@ -496,6 +742,39 @@ impl ClapArgumentLoader {
             }
         }
 
+        fn parse_pairs(x: &clap::ArgMatches, id: &str) -> Result<HashMap<String, String>> {
+            let mut map = HashMap::<String, String>::new();
+            for pair in x.get_many::<String>(id).unwrap_or_default() {
+                match pair.split_once('=') {
+                    | Some((k, v)) => map.insert(k.to_owned(), v.to_owned()),
+                    | None => Err(Error::Argument(format!("{} {} is not of the form KEY=VALUE", id, pair)))?,
+                };
+            }
+            Ok(map)
+        }
+
+        fn parse_secret_args(x: &clap::ArgMatches) -> HashSet<String> {
+            x.get_many::<String>("secret-arg")
+                .unwrap_or_default()
+                .cloned()
+                .collect()
+        }
+
+        fn parse_agent_token(x: &clap::ArgMatches, id: &str) -> Result<String> {
+            match x
+                .get_one::<String>(id)
+                .cloned()
+                .or(std::env::var("NEOMAKE_AGENT_TOKEN").ok())
+            {
+                | Some(v) if !v.is_empty() => Ok(v),
+                | _ => Err(Error::Argument(format!("--{} or NEOMAKE_AGENT_TOKEN must be set", id)))?,
+            }
+        }
+
+        fn parse_history(x: &clap::ArgMatches) -> Option<String> {
+            x.get_one::<String>("history").cloned()
+        }
+
         let cmd = if let Some(subc) = command.subcommand_matches("man") {
             Command::Manual {
                 path: subc.get_one::<String>("out").unwrap().into(),
@@ -527,68 +806,126 @@ impl ClapArgumentLoader {
             } else if let Some(_) = x.subcommand_matches("schema") {
                 Command::WorkflowSchema
             } else {
-                return Err(Error::UnknownCommand.into());
+                return Err(CliError::UnknownCommand.into());
             }
         } else if let Some(x) = command.subcommand_matches("execute") {
-            let mut args_map: HashMap<String, String> = HashMap::new();
-            if let Some(args) = x.get_many::<String>("arg") {
-                for v_arg in args {
-                    let spl: Vec<&str> = v_arg.splitn(2, "=").collect();
-                    args_map.insert(spl[0].to_owned(), spl[1].to_owned());
+            let secrets_map = parse_pairs(x, "secret")?;
+
+            let mut resources_map: HashMap<String, u64> = HashMap::new();
+            if let Some(resources) = x.get_many::<String>("resource") {
+                for v_resource in resources {
+                    match v_resource.split_once('=') {
+                        | Some((k, v)) if v.parse::<u64>().is_ok() => {
+                            resources_map.insert(k.to_owned(), v.parse::<u64>()?);
+                        },
+                        | _ => Err(Error::Argument(format!("invalid resource {}", v_resource)))?,
+                    }
                 }
             }
 
-            let format = Format::from_arg(x.get_one::<String>("format").unwrap().as_str())?;
+            let format = Format::from_str(x.get_one::<String>("format").unwrap().as_str())?;
             let mut plan = String::new();
             std::io::stdin().read_to_string(&mut plan)?;
 
             Command::Execute {
-                plan: format.deserialize::<ExecutionPlan>(&plan)?,
+                plan: Box::new(format.deserialize::<ExecutionPlan>(&plan)?),
                 workers: str::parse::<usize>(x.get_one::<String>("workers").unwrap()).unwrap(),
+                secrets: secrets_map,
+                history: parse_history(x),
                 no_stdout: x.get_flag("no-stdout"),
                 no_stderr: x.get_flag("no-stderr"),
+                memory: match x.get_one::<String>("memory") {
+                    | Some(v) => Some(Resources::parse_memory(v)?),
+                    | None => None,
+                },
+                resources: resources_map,
+                lock_dir: x.get_one::<String>("lock-dir").cloned(),
+                log_dir: x.get_one::<String>("log-dir").cloned(),
+                tee: x.get_flag("tee"),
+                agents: match x.get_one::<String>("agents") {
+                    | Some(v) => {
+                        v.split(',')
+                            .filter(|v| !v.is_empty())
+                            .map(|v| v.to_owned())
+                            .collect_vec()
+                    },
+                    | None => vec![],
+                },
+                agent_token: match x.contains_id("agents") {
+                    | true => parse_agent_token(x, "agent-token")?,
+                    | false => String::new(),
+                },
             }
-        } else if let Some(x) = command.subcommand_matches("plan") {
-            let mut args_map: HashMap<String, String> = HashMap::new();
-            if let Some(args) = x.get_many::<String>("arg") {
-                for v_arg in args {
-                    let spl: Vec<&str> = v_arg.splitn(2, "=").collect();
-                    args_map.insert(spl[0].to_owned(), spl[1].to_owned());
-                }
+        } else if let Some(x) = command.subcommand_matches("agent") {
+            Command::Agent {
+                listen: x.get_one::<String>("listen").unwrap().to_owned(),
+                token: parse_agent_token(x, "token")?,
+                allow_remote: x.get_flag("allow-remote"),
             }
+        } else if let Some(x) = command.subcommand_matches("plan") {
+            let args_map = parse_pairs(x, "arg")?;
 
             Command::Plan {
                 workflow: std::fs::read_to_string(x.get_one::<String>("workflow").unwrap())?,
                 nodes: parse_nodes(x),
                 args: args_map,
-                format: Format::from_arg(x.get_one::<String>("output").unwrap().as_str())?,
+                secret_args: parse_secret_args(x),
+                format: Format::from_str(x.get_one::<String>("output").unwrap().as_str())?,
             }
         } else if let Some(x) = command.subcommand_matches("list") {
             Command::List {
                 workflow: std::fs::read_to_string(x.get_one::<String>("workflow").unwrap())?,
-                format: Format::from_arg(x.get_one::<String>("output").unwrap().as_str())?,
+                format: Format::from_str(x.get_one::<String>("output").unwrap().as_str())?,
             }
         } else if let Some(x) = command.subcommand_matches("describe") {
             Command::Describe {
                 workflow: std::fs::read_to_string(x.get_one::<String>("workflow").unwrap())?,
                 nodes: parse_nodes(x),
-                format: Format::from_arg(x.get_one::<String>("output").unwrap().as_str())?,
+                format: Format::from_str(x.get_one::<String>("output").unwrap().as_str())?,
             }
         } else if let Some(x) = command.subcommand_matches("watch") {
-            let mut args_map: HashMap<String, String> = HashMap::new();
-            if let Some(args) = x.get_many::<String>("arg") {
-                for v_arg in args {
-                    let spl: Vec<&str> = v_arg.splitn(2, "=").collect();
-                    args_map.insert(spl[0].to_owned(), spl[1].to_owned());
-                }
-            }
+            let args_map = parse_pairs(x, "arg")?;
+            let secrets_map = parse_pairs(x, "secret")?;
 
             Command::Watch {
                 workflow: std::fs::read_to_string(x.get_one::<String>("workflow").unwrap())?,
-                watch: x.get_one::<String>("watch").unwrap().to_owned(),
+                watches: x
+                    .get_many::<String>("watch")
+                    .map(|v| v.into_iter().map(|v| v.to_owned()).collect_vec()),
                 args: args_map,
+                secret_args: parse_secret_args(x),
+                secrets: secrets_map,
+                history: parse_history(x),
                 workers: str::parse::<usize>(x.get_one::<String>("workers").unwrap()).unwrap(),
                 root: x.get_one::<String>("root").unwrap().to_owned(),
+                lock_dir: x.get_one::<String>("lock-dir").cloned(),
+                poll: match x.get_one::<String>("poll") {
+                    | Some(v) => Some(str::parse::<u64>(v)?),
+                    | None => None,
+                },
+            }
+        } else if let Some(x) = command.subcommand_matches("history") {
+            let dir = x.get_one::<String>("history").unwrap().to_owned();
+            let format = Format::from_str(x.get_one::<String>("output").unwrap().as_str())?;
+            if let Some(x) = x.subcommand_matches("ls") {
+                Command::HistoryList {
+                    dir,
+                    limit: match x.get_one::<String>("limit") {
+                        | Some(v) => Some(str::parse::<usize>(v)?),
+                        | None => None,
+                    },
+                    format,
+                }
+            } else if let Some(x) = x.subcommand_matches("show") {
+                Command::HistoryShow {
+                    dir,
+                    id: x.get_one::<String>("id").unwrap().to_owned(),
+                    format,
+                }
+            } else if x.subcommand_matches("stats").is_some() {
+                Command::HistoryStats { dir, format }
+            } else {
+                return Err(CliError::UnknownCommand.into());
             }
         } else if let Some(x) = command.subcommand_matches("multiplex") {
             let mut commands = x
@@ -599,12 +936,42 @@ impl ClapArgumentLoader {
             if let Some(file) = x.get_one::<String>("file") {
                 let mut content = String::new();
                 std::fs::File::open(file)?.read_to_string(&mut content)?;
-                let lines = &mut content.lines().map(|v| v.to_owned()).collect::<Vec<_>>();
+                let lines = &mut content
+                    .lines()
+                    .filter(|v| !v.trim().is_empty())
+                    .map(|v| v.to_owned())
+                    .collect::<Vec<_>>();
                 commands.append(lines);
             }
-            Command::Multiplex { commands }
+            let nodes = x
+                .get_many::<String>("node")
+                .unwrap_or_default()
+                .cloned()
+                .collect::<HashSet<_>>();
+            let args_map = parse_pairs(x, "arg")?;
+
+            Command::Multiplex {
+                commands,
+                workflow: match nodes.is_empty() {
+                    | true => None,
+                    | false => Some(std::fs::read_to_string(x.get_one::<String>("workflow").unwrap())?),
+                },
+                nodes,
+                args: args_map,
+                secret_args: parse_secret_args(x),
+                secrets: parse_pairs(x, "secret")?,
+                workers: str::parse::<usize>(x.get_one::<String>("workers").unwrap())?,
+                history: parse_history(x),
+                restart: match x.get_one::<String>("restart").unwrap().as_str() {
+                    | "never" => RestartPolicy::Never,
+                    | "on-failure" => RestartPolicy::OnFailure,
+                    | "always" => RestartPolicy::Always,
+                    | _ => return Err(Error::Argument("unknown restart policy".into()).into()),
+                },
+                exit_on_first_failure: x.get_flag("exit-on-first-failure"),
+            }
         } else {
-            return Err(Error::UnknownCommand.into());
+            return Err(CliError::UnknownCommand.into());
         };
 
         let callargs = CallArgs {
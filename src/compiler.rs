@@ -2,11 +2,19 @@ use {
     crate::{
         error::Error,
         plan,
-        workflow::Workflow,
+        workflow::{
+            Pre,
+            Resources,
+            Stdin,
+            Task,
+            Workflow,
+        },
     },
     anyhow::Result,
+    itertools::Itertools,
     std::{
         collections::{
+            BTreeMap,
             HashMap,
             HashSet,
             VecDeque,
@@ -15,99 +23,267 @@ use {
     },
 };
 
-pub(crate) struct Compiler {
+/// Renders the nodes of a workflow into execution plans.
+pub struct Compiler {
     pub workflow: Workflow,
+    /// Args whose values are not rendered into the plan, see
+    /// [`Compiler::with_secret_args`].
+    pub secret_args: HashSet<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// A node in combination with the parameters it is invoked with.
+pub struct NodeRef {
+    pub node: String,
+    pub args: BTreeMap<String, String>,
+    pub env: BTreeMap<String, String>,
+    pub matrix: Option<String>,
+}
+
+impl NodeRef {
+    /// The unique identifier of this invocation. Equals the node name for
+    /// invocations without parameters. Otherwise the parameters are appended
+    /// as `k=v` (args), `$k=v` (env) and `@matrix=v` with the delimiters in
+    /// keys and values escaped, so distinct invocations never share an id.
+    pub fn id(&self) -> String {
+        let mut params = Vec::<String>::new();
+        params.extend(self.args.iter().map(|(k, v)| format!("{}={}", escape(k), escape(v))));
+        params.extend(self.env.iter().map(|(k, v)| format!("${}={}", escape(k), escape(v))));
+        if let Some(m) = &self.matrix {
+            params.push(format!("@matrix={}", escape(m)));
+        }
+        if params.is_empty() {
+            self.node.clone()
+        } else {
+            format!("{}({})", self.node, params.join(", "))
+        }
+    }
+}
+
+/// Escapes the characters delimiting the parameters in [`NodeRef::id`].
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | ',' | '=' | '(' | ')' | '$' | '@') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl From<&str> for NodeRef {
+    fn from(value: &str) -> Self {
+        Self {
+            node: value.to_owned(),
+            args: BTreeMap::new(),
+            env: BTreeMap::new(),
+            matrix: None,
+        }
+    }
+}
+
+impl From<&Pre> for NodeRef {
+    fn from(value: &Pre) -> Self {
+        match value {
+            | Pre::Name(v) => v.as_str().into(),
+            | Pre::Parameterized(v) => {
+                Self {
+                    node: v.node.clone(),
+                    args: v.args.clone().unwrap_or_default(),
+                    env: v.env.clone().unwrap_or_default(),
+                    matrix: v.matrix.clone(),
+                }
+            },
+        }
+    }
+}
+
+/// The nodes of a workflow, see [`Compiler::list`].
+#[derive(Debug, serde::Serialize)]
+pub struct Listing {
+    pub nodes: Vec<ListedNode>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ListedNode {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre: Option<Vec<Pre>>,
+}
+
+/// The execution stages of nodes, see [`Compiler::describe`].
+#[derive(Debug, serde::Serialize)]
+pub struct Description {
+    pub stages: Vec<Vec<String>>,
 }
 
 impl Compiler {
     pub fn new(wf: Workflow) -> Self {
-        Self { workflow: wf }
+        Self {
+            workflow: wf,
+            secret_args: HashSet::new(),
+        }
+    }
+
+    /// Marks args as secret. They are rendered as placeholders and listed in
+    /// the secrets of the plan, their values are resolved like the ones of
+    /// secret env vars when the plan is executed.
+    pub fn with_secret_args(mut self, names: HashSet<String>) -> Self {
+        self.secret_args = names;
+        self
     }
 
     pub fn plan(&self, nodes: &HashSet<String>, args: &HashMap<String, String>) -> Result<plan::ExecutionPlan> {
+        let nodes = nodes.iter().map(|v| NodeRef::from(v.as_str())).collect_vec();
+        self.plan_with_values(&nodes, args, &serde_json::Map::new())
+    }
+
+    /// Like `plan` but for (parameterized) node references and additionally
+    /// exposes the given (non-string) values to the handlebars placeholders.
+    pub fn plan_with_values(
+        &self,
+        nodes: &[NodeRef],
+        args: &HashMap<String, String>,
+        values: &serde_json::Map<String, serde_json::Value>,
+    ) -> Result<plan::ExecutionPlan> {
         let mut hb = handlebars::Handlebars::new();
         hb.set_strict_mode(true);
-        let arg_vals = self.compile_exec_args(args)?;
         let stages = self.determine_order(nodes)?;
+        if let Some(name) = self.secret_args.iter().find(|v| args.contains_key(*v)) {
+            Err(Error::Argument(format!(
+                "secret arg {} must not be given a value, it is resolved at execution time",
+                name
+            )))?
+        }
+        let secret_args = self
+            .secret_args
+            .iter()
+            .map(|v| (v.clone(), plan::secret_placeholder(v)))
+            .collect::<HashMap<_, _>>();
 
         let mut plan = plan::ExecutionPlan {
             stages: vec![],
             nodes: HashMap::<_, _>::new(),
+            selection: nodes.iter().map(|v| v.id()).sorted().collect(),
+            args: args.clone(),
             env: match &self.workflow.env {
                 | Some(v) => v.compile()?,
                 | None => HashMap::<_, _>::new(),
             },
+            secrets: match &self.workflow.env {
+                | Some(v) => v.compile_secrets()?,
+                | None => vec![],
+            }
+            .into_iter()
+            .chain(self.secret_args.iter().cloned())
+            .sorted()
+            .dedup()
+            .collect(),
+            resources: self.workflow.resources.clone().unwrap_or_default(),
         };
 
         for stage in stages {
             let mut rendered_stage = plan::Stage { nodes: vec![] };
             for node in stage {
-                let node_def = &self.workflow.nodes[&node];
+                let node_def = &self.workflow.nodes[&node.node];
+                let mut node_args = secret_args.clone();
+                node_args.extend(args.clone());
+                node_args.extend(node.args.clone());
+                let mut arg_vals = self.compile_exec_args(&node_args)?;
+                arg_vals.as_object_mut().unwrap().extend(values.clone());
+                let mut node_env = node_def.env.clone().unwrap_or_default();
+                node_env.extend(node.env.clone());
+
+                let parallelism = match (node_def.parallelism, node_def.sequential) {
+                    | (Some(0), _) => {
+                        Err(Error::Argument(format!(
+                            "parallelism of node {} must be at least 1",
+                            node.node
+                        )))?
+                    },
+                    | (Some(v), Some(true)) if v != 1 => {
+                        Err(Error::Argument(format!(
+                            "node {} can not be sequential with a parallelism of {}",
+                            node.node, v
+                        )))?
+                    },
+                    | (_, Some(true)) => Some(1),
+                    | (v, _) => v,
+                };
+                let resources = match &node_def.resources {
+                    | Some(v) => Some(self.compile_resources(v)?),
+                    | None => None,
+                };
                 let mut rendered_node = plan::Node {
                     invocations: vec![],
                     tasks: vec![],
-                    env: match &node_def.env {
-                        | Some(v) => v.clone(),
-                        | None => HashMap::<_, _>::new(),
-                    },
+                    env: node_env,
                     shell: match node_def.shell.clone() {
                         | Some(v) => Some(v.into()),
                         | None => None,
                     },
                     workdir: node_def.workdir.clone(),
+                    executor: node_def.executor.clone(),
+                    sandbox: node_def.sandbox.as_ref().map(|v| {
+                        plan::Sandbox {
+                            outputs: v.outputs.clone().unwrap_or_default(),
+                            network: v.network.unwrap_or(false),
+                        }
+                    }),
+                    resources,
+                    lock: node_def.lock.clone(),
+                    parallelism,
+                    pre: node_def
+                        .pre
+                        .iter()
+                        .flatten()
+                        .map(|v| NodeRef::from(v).id())
+                        .sorted()
+                        .dedup()
+                        .collect(),
                 };
 
                 // default to one matrix entry
                 let invocation_default = vec![crate::plan::Invocation { ..Default::default() }];
 
-                for task in &node_def.tasks {
-                    let rendered_cmd = hb.render_template(&task.script, &arg_vals)?;
-
-                    rendered_node.tasks.push(plan::Task {
-                        cmd: rendered_cmd,
-                        shell: match task.shell.clone() {
-                            | Some(v) => Some(v.into()),
-                            | None => None,
-                        },
-                        env: match task.env.clone() {
-                            | Some(v) => v,
-                            | None => HashMap::<_, _>::new(),
-                        },
-                        workdir: task.workdir.clone(),
-                    });
-                }
+                rendered_node.tasks =
+                    self.compile_tasks(&node_def.tasks, &hb, &arg_vals, node_def.workdir.as_deref(), &mut vec![
+                        node.node.clone(),
+                    ])?;
 
                 rendered_node.invocations = match &node_def.matrix {
                     | Some(m) => m.compile()?,
                     | None => invocation_default,
                 };
+                if let Some(m) = &node.matrix {
+                    let regex = fancy_regex::Regex::new(m)?;
+                    let mut invocations = vec![];
+                    for i in rendered_node.invocations {
+                        if regex.is_match(&i.coords)? {
+                            invocations.push(i);
+                        }
+                    }
+                    rendered_node.invocations = invocations;
+                }
 
-                plan.nodes.insert(node.clone(), rendered_node);
-                rendered_stage.nodes.push(node);
+                plan.nodes.insert(node.id(), rendered_node);
+                rendered_stage.nodes.push(node.id());
             }
+            rendered_stage.nodes.sort();
             plan.stages.push(rendered_stage);
         }
 
         Ok(plan)
     }
 
-    pub async fn list(&self, format: &crate::args::Format) -> Result<()> {
-        #[derive(Debug, serde::Serialize)]
-        struct Output {
-            nodes: Vec<OutputNode>,
-        }
-        #[derive(Debug, serde::Serialize)]
-        struct OutputNode {
-            name: String,
-            #[serde(skip_serializing_if = "Option::is_none")]
-            description: Option<String>,
-            #[serde(skip_serializing_if = "Option::is_none")]
-            pre: Option<Vec<String>>,
-        }
-
-        let mut info = Output {
+    /// Lists all nodes of the workflow, sorted by name.
+    pub fn list(&self) -> Listing {
+        let mut info = Listing {
             nodes: Vec::from_iter(self.workflow.nodes.iter().map(|c| {
-                OutputNode {
+                ListedNode {
                     name: c.0.to_owned(),
                     description: c.1.description.clone(),
                     pre: c.1.pre.clone(),
@@ -115,29 +291,168 @@ impl Compiler {
             })),
         };
         info.nodes.sort_by(|a, b| a.name.cmp(&b.name));
+        info
+    }
 
-        println!("{}", format.serialize(&info)?);
+    /// Describes the stages in which the given nodes and their `pre` nodes
+    /// would be executed.
+    pub fn describe(&self, nodes: &HashSet<String>) -> Result<Description> {
+        let structure = self.determine_order(&nodes.iter().map(|v| NodeRef::from(v.as_str())).collect_vec())?;
 
-        Ok(())
+        let mut info = Description { stages: Vec::new() };
+        for s in structure {
+            info.stages.push(s.iter().map(|s| s.id()).collect::<Vec<_>>());
+        }
+        Ok(info)
     }
 
-    pub async fn describe(&self, nodes: &HashSet<String>, format: &crate::args::Format) -> Result<()> {
-        let structure = self.determine_order(&nodes)?;
+    /// Renders the tasks and inlines the tasks of called nodes.
+    fn compile_tasks(
+        &self,
+        tasks: &[Task],
+        hb: &handlebars::Handlebars,
+        arg_vals: &serde_json::Value,
+        workdir: Option<&str>,
+        chain: &mut Vec<String>,
+    ) -> Result<Vec<plan::Task>> {
+        let render = |v: &str| -> Result<String> { Ok(hb.render_template(v, arg_vals)?) };
 
-        #[derive(Debug, serde::Serialize)]
-        struct Output {
-            stages: Vec<Vec<String>>,
-        }
+        let mut rendered = Vec::<plan::Task>::new();
+        for task in tasks {
+            let kinds = [
+                task.script.is_some(),
+                task.node.is_some(),
+                task.copy.is_some(),
+                task.mkdir.is_some(),
+                task.remove.is_some(),
+                task.template.is_some(),
+                task.wait_for.is_some(),
+            ];
+            if kinds.iter().filter(|v| **v).count() != 1 {
+                Err(Error::InvalidTask(format!(
+                    "exactly one task kind must be set in node {}",
+                    chain.last().unwrap()
+                )))?
+            }
 
-        let mut info = Output { stages: Vec::new() };
-        for s in structure {
-            info.stages
-                .push(s.iter().map(|s| s.to_owned()).into_iter().collect::<Vec<_>>());
-        }
+            if (task.interactive.is_some() || task.stdin.is_some()) && task.script.is_none() {
+                Err(Error::InvalidTask(format!(
+                    "interactive and stdin are only supported for scripts in node {}",
+                    chain.last().unwrap()
+                )))?
+            }
+            if task.interactive.unwrap_or(false) && task.stdin.is_some() {
+                Err(Error::InvalidTask(format!(
+                    "interactive scripts can not have a stdin in node {}",
+                    chain.last().unwrap()
+                )))?
+            }
+            let stdin = match &task.stdin {
+                | Some(Stdin::Content(v)) => Some(plan::Stdin::Content(render(v)?)),
+                | Some(Stdin::File(v)) => Some(plan::Stdin::File(render(&v.file)?)),
+                | None => None,
+            };
 
-        println!("{}", format.serialize(&info)?);
+            let workdir = task.workdir.as_deref().or(workdir);
+            let action = if let Some(v) = &task.script {
+                plan::Action::Script(render(v)?)
+            } else if let Some(v) = &task.node {
+                if chain.contains(v) {
+                    return Err(Error::NodeRecursion.into());
+                }
+                let node_def = match self.workflow.nodes.get(v) {
+                    | Some(v) => v,
+                    | None => return Err(Error::NotFound(v.to_owned()).into()),
+                };
+                chain.push(v.clone());
+                let inlined = self.compile_tasks(
+                    &node_def.tasks,
+                    hb,
+                    arg_vals,
+                    node_def.workdir.as_deref().or(workdir),
+                    chain,
+                )?;
+                chain.pop();
+
+                for mut t in inlined {
+                    let mut env = task.env.clone().unwrap_or_default();
+                    env.extend(node_def.env.clone().unwrap_or_default());
+                    env.extend(t.env);
+                    t.env = env;
+                    t.shell = t
+                        .shell
+                        .or(node_def.shell.clone().map(|v| v.into()))
+                        .or(task.shell.clone().map(|v| v.into()));
+                    t.workdir = t.workdir.or(node_def.workdir.clone()).or(task.workdir.clone());
+                    rendered.push(t);
+                }
+                continue;
+            } else if let Some(v) = &task.copy {
+                plan::Action::Copy {
+                    from: render(&v.from)?,
+                    to: render(&v.to)?,
+                }
+            } else if let Some(v) = &task.mkdir {
+                plan::Action::Mkdir(render(v)?)
+            } else if let Some(v) = &task.remove {
+                plan::Action::Remove(render(v)?)
+            } else if let Some(v) = &task.template {
+                let src = std::path::Path::new(workdir.unwrap_or(".")).join(render(&v.src)?);
+                plan::Action::Write {
+                    path: render(&v.dst)?,
+                    content: render(&std::fs::read_to_string(src)?)?,
+                }
+            } else if let Some(v) = &task.wait_for {
+                let target = match (&v.tcp, &v.file) {
+                    | (Some(tcp), None) => plan::WaitTarget::Tcp(render(tcp)?),
+                    | (None, Some(file)) => plan::WaitTarget::File(render(file)?),
+                    | _ => {
+                        Err(Error::InvalidTask(format!(
+                            "exactly one of tcp or file must be set for wait_for in node {}",
+                            chain.last().unwrap()
+                        )))?
+                    },
+                };
+                plan::Action::WaitFor {
+                    target,
+                    timeout: v.timeout.unwrap_or(30) * 1000,
+                    interval: v.interval.unwrap_or(500),
+                }
+            } else {
+                unreachable!()
+            };
 
-        Ok(())
+            rendered.push(plan::Task {
+                action,
+                shell: task.shell.clone().map(|v| v.into()),
+                env: task.env.clone().unwrap_or_default(),
+                workdir: task.workdir.clone(),
+                interactive: task.interactive.unwrap_or(false),
+                stdin,
+            });
+        }
+        Ok(rendered)
+    }
+
+    fn compile_resources(&self, resources: &Resources) -> Result<plan::Resources> {
+        for name in resources.named.keys() {
+            if !self.workflow.resources.iter().any(|v| v.contains_key(name)) {
+                Err(Error::NotFound(format!("resource {}", name)))?
+            }
+        }
+        let memory = match &resources.memory {
+            | Some(v) => Some(Resources::parse_memory(v)?),
+            | None => None,
+        };
+        if resources.limit_memory.unwrap_or(false) && memory.is_none() {
+            Err(Error::Argument("limit_memory requires memory to be set".to_owned()))?
+        }
+        Ok(plan::Resources {
+            cpu: resources.cpu.unwrap_or(1),
+            memory,
+            limit_memory: resources.limit_memory.unwrap_or(false),
+            named: resources.named.clone().into_iter().collect(),
+        })
     }
 
     fn compile_exec_args(&self, args: &HashMap<String, String>) -> Result<serde_json::Value> {
@@ -174,12 +489,34 @@ impl Compiler {
         Ok(values_json)
     }
 
-    fn determine_order(&self, exec: &HashSet<String>) -> Result<Vec<HashSet<String>>> {
-        let mut map = HashMap::<String, Vec<String>>::new();
-
+    /// Collects the `inputs` globs of the given nodes and all of their
+    /// transitive `pre` nodes.
+    pub fn inputs(&self, nodes: &[NodeRef]) -> Result<Vec<String>> {
         let mut seen = HashSet::<String>::new();
-        let mut pending = VecDeque::<String>::new();
-        pending.extend(exec.to_owned());
+        let mut pending = nodes.iter().map(|n| n.node.clone()).collect::<Vec<_>>();
+        let mut inputs = Vec::<String>::new();
+        while let Some(next) = pending.pop() {
+            if !seen.insert(next.clone()) {
+                continue;
+            }
+            let node = match self.workflow.nodes.get(&next) {
+                | Some(v) => v,
+                | None => Err(Error::NotFound(next))?,
+            };
+            inputs.extend(node.inputs.clone().unwrap_or_default());
+            if let Some(pre) = &node.pre {
+                pending.extend(pre.iter().map(|p| NodeRef::from(p).node));
+            }
+        }
+        Ok(inputs.into_iter().unique().collect())
+    }
+
+    fn determine_order(&self, exec: &[NodeRef]) -> Result<Vec<HashSet<NodeRef>>> {
+        let mut map = HashMap::<NodeRef, Vec<NodeRef>>::new();
+
+        let mut seen = HashSet::<NodeRef>::new();
+        let mut pending = VecDeque::<NodeRef>::new();
+        pending.extend(exec.iter().cloned());
 
         while let Some(next) = pending.pop_back() {
             if seen.contains(&next) {
@@ -187,21 +524,22 @@ impl Compiler {
             }
             seen.insert(next.clone());
 
-            let c = self.workflow.nodes.get(&next);
+            let c = self.workflow.nodes.get(&next.node);
             if c.is_none() {
-                return Err(Error::NotFound(next.to_owned()).into());
+                return Err(Error::NotFound(next.node.to_owned()).into());
             }
 
             if let Some(pre) = &c.unwrap().pre {
+                let pre = pre.iter().map(NodeRef::from).collect::<Vec<_>>();
                 map.insert(next, pre.clone());
-                pending.extend(pre.clone());
+                pending.extend(pre);
             } else {
-                map.insert(next, Vec::<String>::new());
+                map.insert(next, Vec::<NodeRef>::new());
             }
         }
         seen.clear();
 
-        let mut result = Vec::<HashSet<String>>::new();
+        let mut result = Vec::<HashSet<NodeRef>>::new();
         while map.len() > 0 {
             // This implementation SHOULD make use of the unstable hash_drain_filter feature
             // to use the drain_filter method on the hashmap but it's not allowed on stable
@@ -226,9 +564,159 @@ impl Compiler {
             }
             let set = leafs.iter().map(|x| x.0.clone());
             seen.extend(set.clone());
-            result.push(HashSet::<String>::from_iter(set));
+            result.push(HashSet::<NodeRef>::from_iter(set));
         }
 
         Ok(result)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compiler(workflow: &str) -> Compiler {
+        Compiler::new(Workflow::load(workflow).unwrap())
+    }
+
+    const SECRETS: &str = r#"
+version: "0.0"
+env:
+  secret: "^TOKEN$"
+  vars:
+    TOKEN: "ignored"
+    PLAIN: "p"
+nodes:
+  a:
+    tasks:
+      - script: "echo {{ args.token }} {{ args.plain }}"
+"#;
+
+    #[test]
+    fn secret_args_are_not_rendered() {
+        let c = compiler(SECRETS).with_secret_args(HashSet::from(["args.token".to_owned()]));
+        let plan = c
+            .plan(
+                &HashSet::from(["a".to_owned()]),
+                &HashMap::from([("args.plain".to_owned(), "x".to_owned())]),
+            )
+            .unwrap();
+        match &plan.nodes["a"].tasks[0].action {
+            | plan::Action::Script(v) => assert_eq!(v, "echo {{secret:args.token}} x"),
+            | v => panic!("unexpected action {:?}", v),
+        }
+        assert_eq!(plan.secrets, vec!["TOKEN".to_owned(), "args.token".to_owned()]);
+        assert_eq!(plan.env, HashMap::from([("PLAIN".to_owned(), "p".to_owned())]));
+        assert!(!plan.args.contains_key("args.token"));
+    }
+
+    #[test]
+    fn secret_args_must_not_have_values() {
+        let c = compiler(SECRETS).with_secret_args(HashSet::from(["args.token".to_owned()]));
+        let res = c.plan(
+            &HashSet::from(["a".to_owned()]),
+            &HashMap::from([
+                ("args.token".to_owned(), "clear".to_owned()),
+                ("args.plain".to_owned(), "x".to_owned()),
+            ]),
+        );
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<Error>(),
+            Some(Error::Argument(_))
+        ));
+    }
+
+    const PRE: &str = r#"
+version: "0.0"
+nodes:
+  deploy:
+    matrix:
+      dense:
+        dimensions:
+          - - env:
+                X: "0"
+            - env:
+                X: "1"
+    tasks:
+      - script: "echo {{ args.env }}"
+  release:
+    pre:
+      - deploy
+      - node: deploy
+        args:
+          args.env: staging
+        matrix: "^1$"
+      - node: deploy
+        args:
+          args.env: staging
+        matrix: "^1$"
+    tasks:
+      - script: "echo release"
+"#;
+
+    #[test]
+    fn parameterized_pre_nodes_are_planned_separately() {
+        let plan = compiler(PRE)
+            .plan(
+                &HashSet::from(["release".to_owned()]),
+                &HashMap::from([("args.env".to_owned(), "prod".to_owned())]),
+            )
+            .unwrap();
+        let staging = "deploy(args.env=staging, @matrix=^1\\$)";
+        assert_eq!(plan.stages.len(), 2);
+        assert_eq!(plan.stages[0].nodes, vec!["deploy".to_owned(), staging.to_owned()]);
+        assert_eq!(plan.nodes["release"].pre, vec!["deploy".to_owned(), staging.to_owned()]);
+        match &plan.nodes[staging].tasks[0].action {
+            | plan::Action::Script(v) => assert_eq!(v, "echo staging"),
+            | v => panic!("unexpected action {:?}", v),
+        }
+        match &plan.nodes["deploy"].tasks[0].action {
+            | plan::Action::Script(v) => assert_eq!(v, "echo prod"),
+            | v => panic!("unexpected action {:?}", v),
+        }
+    }
+
+    #[test]
+    fn matrix_regex_filters_invocations() {
+        let plan = compiler(PRE)
+            .plan(
+                &HashSet::from(["release".to_owned()]),
+                &HashMap::from([("args.env".to_owned(), "prod".to_owned())]),
+            )
+            .unwrap();
+        let coords = |id: &str| {
+            plan.nodes[id]
+                .invocations
+                .iter()
+                .map(|v| v.coords.clone())
+                .collect_vec()
+        };
+        assert_eq!(coords("deploy"), vec!["0".to_owned(), "1".to_owned()]);
+        assert_eq!(coords("deploy(args.env=staging, @matrix=^1\\$)"), vec!["1".to_owned()]);
+    }
+
+    #[test]
+    fn node_ids_escape_parameters() {
+        let joined = NodeRef {
+            args: BTreeMap::from([("a".to_owned(), "1, b=2".to_owned())]),
+            ..NodeRef::from("n")
+        };
+        let separate = NodeRef {
+            args: BTreeMap::from([("a".to_owned(), "1".to_owned()), ("b".to_owned(), "2".to_owned())]),
+            ..NodeRef::from("n")
+        };
+        let matrix_arg = NodeRef {
+            args: BTreeMap::from([("@matrix".to_owned(), "x".to_owned())]),
+            ..NodeRef::from("n")
+        };
+        let matrix = NodeRef {
+            matrix: Some("x".to_owned()),
+            ..NodeRef::from("n")
+        };
+        assert_eq!(joined.id(), "n(a=1\\, b\\=2)");
+        assert_eq!(separate.id(), "n(a=1, b=2)");
+        assert_eq!(matrix_arg.id(), "n(\\@matrix=x)");
+        assert_eq!(matrix.id(), "n(@matrix=x)");
+        assert_eq!(NodeRef::from("n").id(), "n");
+    }
+}
@@ -1,23 +1,43 @@
+/// Errors raised by `neomake`. They are returned wrapped in an
+/// [`anyhow::Error`] and can be inspected with
+/// [`anyhow::Error::downcast_ref`].
 #[derive(Debug, thiserror::Error)]
-pub(crate) enum Error {
+#[non_exhaustive]
+pub enum Error {
     // #[error("generic {0}")]
     // Generic(String),
+    /// Several tasks of a stage failed, one error per failed task.
     #[error("many: {0:?}")]
     Many(Vec<anyhow::Error>),
-    #[error("experimental command: {0}")]
-    ExperimentalCommand(String),
+    /// An argument could not be parsed or is invalid.
     #[error("argument {0}")]
     Argument(String),
+    /// A task process could not be spawned or exited unsuccessfully.
     #[error("child process {0}")]
     ChildProcess(String),
+    /// The `pre` nodes of the workflow form a cycle.
     #[error("node recursion")]
     NodeRecursion,
-    #[error("unknown command")]
-    UnknownCommand,
+    /// The `extends` chain of a node or template forms a cycle, listed as
+    /// `a -> b -> a`.
+    #[error("template recursion {0}")]
+    TemplateRecursion(String),
+    /// The workflow version is not supported by this version of `neomake`.
     #[error("version compatibility {0}")]
     VersionCompatibility(String),
+    /// A node, watch, path or history record does not exist.
     #[error("not found {0}")]
     NotFound(String),
+    /// A task definition is invalid.
+    #[error("invalid task {0}")]
+    InvalidTask(String),
+    /// The execution was cancelled before it finished.
+    #[error("cancelled")]
+    Cancelled,
+    /// A task or node exceeded its timeout.
+    #[error("timeout {0}")]
+    Timeout(String),
+    /// A node name does not match the allowed pattern.
     #[error("invalid node name {0}")]
     InvalidNodeName(String),
 }
@@ -1,49 +1,762 @@
 use {
     crate::{
         error::Error,
+        executor::{
+            self,
+            Executor,
+            LocalExecutor,
+            Output,
+            ResolvedTask,
+            Spawn,
+            Termination,
+            Terminator,
+        },
+        history::{
+            History,
+            Record,
+            TaskRecord,
+        },
         plan,
     },
     anyhow::Result,
+    itertools::Itertools,
     std::{
-        collections::HashMap,
-        process::Stdio,
+        collections::{
+            HashMap,
+            VecDeque,
+        },
+        io::{
+            BufRead,
+            BufReader,
+            Read,
+            Write,
+        },
+        net::TcpStream,
+        os::fd::AsRawFd,
+        path::{
+            Path,
+            PathBuf,
+        },
+        sync::{
+            Arc,
+            Mutex,
+        },
+        time::{
+            Duration,
+            Instant,
+        },
     },
-    threadpool::ThreadPool,
 };
 
+/// Forwards the output of a child process line by line while replacing every
+/// occurrence of a secret value.
+fn forward_masked(from: impl Read, mut to: impl Write, masks: &[String]) -> Result<()> {
+    let mut reader = BufReader::new(from);
+    let mut buf = Vec::<u8>::new();
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            break;
+        }
+        let mut line = String::from_utf8_lossy(&buf).into_owned();
+        for m in masks {
+            line = line.replace(m, "***");
+        }
+        to.write_all(line.as_bytes())?;
+        to.flush()?;
+    }
+    Ok(())
+}
+
+/// Encodes the value as a single path component by percent-encoding every
+/// byte outside of `[A-Za-z0-9_,=-]`, so that it contains neither separators
+/// nor dots. Different values always result in different components.
+fn path_component(v: &str) -> String {
+    let mut encoded = String::new();
+    for b in v.bytes() {
+        match b {
+            | b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'_' | b',' | b'=' | b'-' => encoded.push(b as char),
+            | b => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+/// Sends every written chunk (a line when used by `forward_masked`) to the
+/// channel.
+struct SinkWriter(flume::Sender<String>);
+
+impl Write for SinkWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .send(String::from_utf8_lossy(buf).into_owned())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::BrokenPipe, e))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes every chunk to the log file of a task (shared by its stdout and
+/// stderr) and forwards it if the stream is also shown.
+struct LogWriter {
+    file: Arc<Mutex<std::fs::File>>,
+    to: Option<Box<dyn Write+Send>>,
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.lock().unwrap().write_all(buf)?;
+        if let Some(to) = &mut self.to {
+            to.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.lock().unwrap().flush()?;
+        match &mut self.to {
+            | Some(to) => to.flush(),
+            | None => Ok(()),
+        }
+    }
+}
+
+/// Which output streams of the tasks are forwarded.
 #[derive(Debug, Clone)]
-pub(crate) struct OutputMode {
+pub struct OutputMode {
     pub stderr: bool,
     pub stdout: bool,
 }
 
-pub(crate) struct ExecutionEngine {
+impl Default for OutputMode {
+    fn default() -> Self {
+        Self {
+            stderr: true,
+            stdout: true,
+        }
+    }
+}
+
+#[derive(Default)]
+struct CancellationState {
+    cancelled: bool,
+    /// Whether the running tasks have been killed.
+    killed: bool,
+    /// Terminators of the running tasks.
+    tasks: HashMap<u64, Terminator>,
+    next: u64,
+}
+
+impl std::fmt::Debug for CancellationState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CancellationState")
+            .field("cancelled", &self.cancelled)
+            .field("killed", &self.killed)
+            .field("tasks", &self.tasks.len())
+            .finish()
+    }
+}
+
+/// A handle for terminating a running execution. Tasks that have not been
+/// started yet are skipped and running tasks are terminated.
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation {
+    state: Arc<Mutex<CancellationState>>,
+}
+
+impl Cancellation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.cancelled = true;
+        for terminate in state.tasks.values() {
+            terminate(Termination::Graceful);
+        }
+    }
+
+    /// Cancels the execution and kills the running tasks, for tasks that do
+    /// not exit after [`Cancellation::cancel`].
+    pub fn kill(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.cancelled = true;
+        state.killed = true;
+        for terminate in state.tasks.values() {
+            terminate(Termination::Forced);
+        }
+    }
+
+    /// Cancels the execution and waits for the given thread running it to
+    /// finish. Kills the running tasks if it did not finish within
+    /// [`TERMINATION_GRACE`](executor::TERMINATION_GRACE).
+    pub fn terminate<T>(&self, handle: std::thread::JoinHandle<T>) -> std::thread::Result<T> {
+        self.cancel();
+        let started = Instant::now();
+        while !handle.is_finished() && started.elapsed() < executor::TERMINATION_GRACE {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        if !handle.is_finished() {
+            self.kill();
+        }
+        handle.join()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.lock().unwrap().cancelled
+    }
+
+    /// Registers a running task. Terminates it right away if the execution
+    /// has been cancelled in the meantime.
+    fn register(&self, terminate: Terminator) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        if state.killed {
+            terminate(Termination::Forced);
+        } else if state.cancelled {
+            terminate(Termination::Graceful);
+        }
+        state.next += 1;
+        let id = state.next;
+        state.tasks.insert(id, terminate);
+        id
+    }
+
+    fn unregister(&self, id: u64) {
+        self.state.lock().unwrap().tasks.remove(&id);
+    }
+}
+
+/// Settings that are shared by all tasks of an execution.
+struct Context {
+    output: OutputMode,
+    /// Secret values to mask in the output.
+    masks: Vec<String>,
+    cancellation: Option<Cancellation>,
+    /// Receives the output lines instead of the terminal.
+    sink: Option<flume::Sender<String>>,
+    /// The placeholders of secret args with their values.
+    placeholders: Vec<(String, String)>,
+    /// Whether the output is forwarded as well when it is written to log
+    /// files.
+    tee: bool,
+}
+
+impl Context {
+    /// Replaces the placeholders of secret args with their values.
+    fn reveal_str(&self, v: &str) -> String {
+        let mut v = v.to_owned();
+        for (placeholder, value) in &self.placeholders {
+            v = v.replace(placeholder, value);
+        }
+        v
+    }
+
+    /// Replaces the placeholders in all values of the action.
+    fn reveal(&self, action: &plan::Action) -> plan::Action {
+        let r = |v: &String| self.reveal_str(v);
+        match action {
+            | plan::Action::Script(v) => plan::Action::Script(r(v)),
+            | plan::Action::Copy { from, to } => {
+                plan::Action::Copy {
+                    from: r(from),
+                    to: r(to),
+                }
+            },
+            | plan::Action::Mkdir(v) => plan::Action::Mkdir(r(v)),
+            | plan::Action::Remove(v) => plan::Action::Remove(r(v)),
+            | plan::Action::Write { path, content } => {
+                plan::Action::Write {
+                    path: r(path),
+                    content: r(content),
+                }
+            },
+            | plan::Action::WaitFor {
+                target,
+                timeout,
+                interval,
+            } => {
+                plan::Action::WaitFor {
+                    target: match target {
+                        | plan::WaitTarget::Tcp(v) => plan::WaitTarget::Tcp(r(v)),
+                        | plan::WaitTarget::File(v) => plan::WaitTarget::File(r(v)),
+                    },
+                    timeout: *timeout,
+                    interval: *interval,
+                }
+            },
+        }
+    }
+}
+
+struct Work {
+    executor: Arc<dyn Executor>,
+    sandbox: Option<plan::Sandbox>,
+    memory_limit: Option<u64>,
+    workdir: Option<String>,
+    env: HashMap<String, String>,
+    shell: plan::Shell,
+    action: plan::Action,
+    interactive: bool,
+    stdin: Option<plan::Stdin>,
+    /// The file the output of a script is written to.
+    log: Option<PathBuf>,
+}
+
+impl Work {
+    /// Runs the work and returns the exit code (0 for all non-script actions).
+    fn run(&self, ctx: &Context) -> Result<Option<i32>> {
+        if let Some(c) = &ctx.cancellation {
+            if c.is_cancelled() {
+                Err(Error::Cancelled)?
+            }
+        }
+
+        // paths of file operations are relative to the workdir
+        let path = |v: &str| -> PathBuf {
+            match &self.workdir {
+                | Some(w) => Path::new(w).join(v),
+                | None => PathBuf::from(v),
+            }
+        };
+
+        match &ctx.reveal(&self.action) {
+            | plan::Action::Script(command) => {
+                let stdin = match &self.stdin {
+                    | Some(plan::Stdin::Content(v)) => Some(ctx.reveal_str(v).into_bytes()),
+                    | Some(plan::Stdin::File(v)) => Some(std::fs::read(path(&ctx.reveal_str(v)))?),
+                    | None => None,
+                };
+                return self.run_script(command, stdin, ctx);
+            },
+            | plan::Action::Copy { from, to } => {
+                let (from, to) = (path(from), path(to));
+                if from.is_dir() {
+                    for entry in walkdir::WalkDir::new(&from) {
+                        let entry = entry?;
+                        let target = to.join(entry.path().strip_prefix(&from)?);
+                        if entry.file_type().is_dir() {
+                            std::fs::create_dir_all(target)?;
+                        } else {
+                            std::fs::copy(entry.path(), target)?;
+                        }
+                    }
+                } else {
+                    std::fs::copy(from, to)?;
+                }
+            },
+            | plan::Action::Mkdir(v) => std::fs::create_dir_all(path(v))?,
+            | plan::Action::Remove(v) => {
+                let p = path(v);
+                match std::fs::symlink_metadata(&p) {
+                    | Ok(m) if m.is_dir() => std::fs::remove_dir_all(p)?,
+                    | Ok(_) => std::fs::remove_file(p)?,
+                    | Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+                    | Err(e) => Err(e)?,
+                };
+            },
+            | plan::Action::Write { path: p, content } => {
+                let p = path(p);
+                if let Some(parent) = p.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(p, content)?;
+            },
+            | plan::Action::WaitFor {
+                target,
+                timeout,
+                interval,
+            } => {
+                let started = Instant::now();
+                loop {
+                    let ready = match target {
+                        | plan::WaitTarget::Tcp(addr) => TcpStream::connect(addr).is_ok(),
+                        | plan::WaitTarget::File(f) => path(f).exists(),
+                    };
+                    if ready {
+                        break;
+                    }
+                    if started.elapsed() >= Duration::from_millis(*timeout) {
+                        Err(Error::Timeout(format!("waiting for {}", target)))?
+                    }
+                    std::thread::sleep(Duration::from_millis(*interval));
+                }
+            },
+        };
+        Ok(Some(0))
+    }
+
+    fn run_script(&self, command: &str, stdin: Option<Vec<u8>>, ctx: &Context) -> Result<Option<i32>> {
+        if self.interactive && ctx.sink.is_some() {
+            Err(Error::InvalidTask("interactive scripts require a terminal".to_owned()))?
+        }
+        let task = ResolvedTask {
+            shell: self.shell.clone(),
+            command: command.to_owned(),
+            env: self.env.clone(),
+            workdir: self.workdir.clone(),
+            sandbox: self.sandbox.clone(),
+            memory_limit: self.memory_limit,
+            interactive: self.interactive,
+            stdin,
+        };
+
+        // the output of interactive scripts is not logged
+        let log = match &self.log {
+            | Some(path) if !self.interactive => {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                Some(Arc::new(Mutex::new(std::fs::File::create(path)?)))
+            },
+            | _ => None,
+        };
+        let shown = |enabled: bool| enabled && (log.is_none() || ctx.tee);
+
+        let mask_output = !ctx.masks.is_empty() || ctx.sink.is_some();
+        let output = |enabled: bool| {
+            // the output of interactive scripts is neither masked nor discarded
+            match (self.interactive, enabled, mask_output) {
+                | (true, ..) => Output::Inherit,
+                | (false, ..) if log.is_some() => Output::Piped,
+                | (false, false, _) => Output::Null,
+                | (false, true, false) => Output::Inherit,
+                | (false, true, true) => Output::Piped,
+            }
+        };
+        let mut process = self.executor.spawn(&task, Spawn {
+            stdout: output(ctx.output.stdout),
+            stderr: output(ctx.output.stderr),
+            terminable: ctx.cancellation.is_some(),
+        })?;
+
+        let registration = ctx.cancellation.as_ref().map(|c| c.register(process.terminator()));
+        let forward = |from: Option<Box<dyn Read+Send>>, enabled: bool, terminal: fn() -> Box<dyn Write+Send>| {
+            from.map(|v| {
+                let masks = ctx.masks.clone();
+                let to = match (shown(enabled), &ctx.sink) {
+                    | (false, _) => None,
+                    | (true, Some(s)) => Some(Box::new(SinkWriter(s.clone())) as Box<dyn Write+Send>),
+                    | (true, None) => Some(terminal()),
+                };
+                let to = match (&log, to) {
+                    | (Some(file), to) => Some(Box::new(LogWriter { file: file.clone(), to }) as Box<dyn Write+Send>),
+                    | (None, to) => to,
+                };
+                std::thread::spawn(move || {
+                    match to {
+                        | Some(to) => forward_masked(v, to, &masks),
+                        | None => forward_masked(v, std::io::sink(), &masks),
+                    }
+                })
+            })
+        };
+        let forwards = [
+            forward(process.stdout(), ctx.output.stdout, || Box::new(std::io::stdout())),
+            forward(process.stderr(), ctx.output.stderr, || Box::new(std::io::stderr())),
+        ];
+        let status = process.wait();
+        for f in forwards.into_iter().flatten() {
+            f.join().expect("output forwarding panicked")?;
+        }
+        if let (Some(c), Some(id)) = (&ctx.cancellation, registration) {
+            c.unregister(id);
+            if c.is_cancelled() {
+                Err(Error::Cancelled)?
+            }
+        }
+
+        status
+    }
+}
+
+/// The outcome of [`ExecutionEngine::execute_recorded`].
+pub struct Recorded {
+    /// The records of all tasks that were run.
+    pub tasks: Vec<TaskRecord>,
+    pub result: Result<()>,
+    /// Why the run could not be stored in the history, if so. A run does not
+    /// fail because it could not be recorded.
+    pub history: Option<anyhow::Error>,
+}
+
+/// Executes plans stage by stage. Configured through the `with_*` methods,
+/// starting from the defaults (all output forwarded, one worker, no secrets and
+/// no history).
+#[derive(Clone)]
+pub struct ExecutionEngine {
     pub output: OutputMode,
+    /// The worker capacity, the number of invocations that are executed in
+    /// parallel unless nodes declare other `cpu` resources.
+    pub workers: usize,
+    /// The memory capacity in bytes. Memory resources of nodes are ignored if
+    /// not set.
+    pub memory: Option<u64>,
+    /// Capacities of named resources, replacing the ones of the plan.
+    pub resources: HashMap<String, u64>,
+    /// The directory of the lock files that enforce node locks across
+    /// processes. Locks only apply within the execution if not set.
+    pub lock_dir: Option<PathBuf>,
+    /// Secret env vars that are passed to every task and masked in its output.
+    pub secrets: HashMap<String, String>,
+    /// Where to record the runs.
+    pub history: Option<History>,
+    /// The directory the output of every script is written to, as
+    /// `<node>/<coords>/<task index>.log`.
+    pub log_dir: Option<PathBuf>,
+    /// Whether the output is forwarded as well when it is written to log
+    /// files.
+    pub tee: bool,
+    /// Receives the output lines of the tasks instead of the terminal.
+    pub sink: Option<flume::Sender<String>>,
+    /// The executors nodes can select by name. Contains the
+    /// [`LocalExecutor`] as `local`.
+    pub executors: HashMap<String, Arc<dyn Executor>>,
+    /// The executor for nodes that do not select one.
+    pub default_executor: String,
+}
+
+impl Default for ExecutionEngine {
+    fn default() -> Self {
+        Self {
+            output: OutputMode::default(),
+            workers: 1,
+            memory: None,
+            resources: HashMap::new(),
+            lock_dir: None,
+            secrets: HashMap::new(),
+            history: None,
+            log_dir: None,
+            tee: false,
+            sink: None,
+            executors: HashMap::from([(executor::LOCAL.to_owned(), Arc::new(LocalExecutor) as Arc<dyn Executor>)]),
+            default_executor: executor::LOCAL.to_owned(),
+        }
+    }
 }
 
 impl ExecutionEngine {
-    pub fn new(output: OutputMode) -> Self {
-        Self { output }
+    pub fn with_output(mut self, output: OutputMode) -> Self {
+        self.output = output;
+        self
+    }
+
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    pub fn with_memory(mut self, memory: u64) -> Self {
+        self.memory = Some(memory);
+        self
+    }
+
+    pub fn with_resources(mut self, resources: HashMap<String, u64>) -> Self {
+        self.resources = resources;
+        self
+    }
+
+    pub fn with_lock_dir(mut self, lock_dir: PathBuf) -> Self {
+        self.lock_dir = Some(lock_dir);
+        self
+    }
+
+    pub fn with_secrets(mut self, secrets: HashMap<String, String>) -> Self {
+        self.secrets = secrets;
+        self
+    }
+
+    pub fn with_history(mut self, history: History) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    pub fn with_log_dir(mut self, log_dir: PathBuf) -> Self {
+        self.log_dir = Some(log_dir);
+        self
+    }
+
+    pub fn with_tee(mut self, tee: bool) -> Self {
+        self.tee = tee;
+        self
     }
 
-    pub fn execute(&self, plan: &plan::ExecutionPlan, workers: usize) -> Result<()> {
-        struct Work {
-            workdir: Option<String>,
-            env: HashMap<String, String>,
-            shell: plan::Shell,
-            command: String,
+    pub fn with_sink(mut self, sink: flume::Sender<String>) -> Self {
+        self.sink = Some(sink);
+        self
+    }
+
+    /// Registers an executor under the given name, replacing any executor
+    /// with the same name.
+    pub fn with_executor(mut self, name: &str, executor: Arc<dyn Executor>) -> Self {
+        self.executors.insert(name.to_owned(), executor);
+        self
+    }
+
+    /// Selects the executor for nodes that do not select one themselves.
+    pub fn with_default_executor(mut self, name: &str) -> Self {
+        self.default_executor = name.to_owned();
+        self
+    }
+
+    /// Resolves the secrets referenced by the plan from the environment unless
+    /// given to the engine and merges them with the secrets given to the
+    /// engine.
+    fn resolve_secrets(&self, plan: &plan::ExecutionPlan) -> Result<HashMap<String, String>> {
+        let mut secrets = HashMap::<String, String>::new();
+        for name in plan.secrets.iter().filter(|v| !self.secrets.contains_key(*v)) {
+            match std::env::var(name) {
+                | Ok(v) => secrets.insert(name.clone(), v),
+                | Err(_) => Err(Error::NotFound(format!("secret {} in environment", name)))?,
+            };
+        }
+        secrets.extend(self.secrets.clone());
+        Ok(secrets)
+    }
+
+    /// Executes the plan and records it in the history if configured. Fails
+    /// with [`Error::Many`] containing the errors of the tasks of the first
+    /// stage that failed. Failures to record the run are only returned by
+    /// [`ExecutionEngine::execute_recorded`].
+    pub fn execute(&self, plan: &plan::ExecutionPlan) -> Result<()> {
+        self.execute_with(plan, None)
+    }
+
+    /// Executes the plan while allowing it to be terminated through the given
+    /// cancellation handle.
+    pub fn execute_cancellable(&self, plan: &plan::ExecutionPlan, cancellation: &Cancellation) -> Result<()> {
+        self.execute_with(plan, Some(cancellation.clone()))
+    }
+
+    fn execute_with(&self, plan: &plan::ExecutionPlan, cancellation: Option<Cancellation>) -> Result<()> {
+        self.execute_recorded(plan, cancellation).result
+    }
+
+    /// The args of the plan as recorded in the history, without secret args
+    /// and with the values of all secrets masked.
+    fn record_args(&self, plan: &plan::ExecutionPlan) -> HashMap<String, String> {
+        let secrets = self.resolve_secrets(plan).unwrap_or_else(|_| self.secrets.clone());
+        plan.args
+            .iter()
+            .filter(|(k, _)| !plan.secrets.contains(k))
+            .map(|(k, v)| {
+                let v = secrets
+                    .values()
+                    .filter(|v| !v.is_empty())
+                    .fold(v.clone(), |acc, s| acc.replace(s, "***"));
+                (k.clone(), v)
+            })
+            .collect()
+    }
+
+    /// Executes the plan, records it in the history if configured and returns
+    /// the records of all tasks that were run alongside the result.
+    pub fn execute_recorded(&self, plan: &plan::ExecutionPlan, cancellation: Option<Cancellation>) -> Recorded {
+        let started = History::now();
+        let mut records = Vec::<TaskRecord>::new();
+        let res = self.execute_stages(plan, cancellation, &mut records);
+
+        let mut history_error = None;
+        if let Some(history) = &self.history {
+            let record = Record {
+                id: format!("{}-{}", started.as_millis(), std::process::id()),
+                timestamp: started.as_secs(),
+                plan: History::hash(plan).unwrap_or_default(),
+                nodes: plan.selection.clone(),
+                args: self.record_args(plan),
+                duration: (History::now() - started).as_millis() as u64,
+                success: res.is_ok(),
+                tasks: records.clone(),
+            };
+            history_error = history.store(&record).err();
+        }
+        Recorded {
+            tasks: records,
+            result: res,
+            history: history_error,
+        }
+    }
+
+    fn execute_stages(
+        &self,
+        plan: &plan::ExecutionPlan,
+        cancellation: Option<Cancellation>,
+        records: &mut Vec<TaskRecord>,
+    ) -> Result<()> {
+        let secrets = self.resolve_secrets(plan)?;
+        let ctx = Arc::new(Context {
+            output: self.output.clone(),
+            masks: secrets.values().filter(|v| !v.is_empty()).cloned().collect_vec(),
+            cancellation,
+            sink: self.sink.clone(),
+            placeholders: secrets
+                .iter()
+                .map(|(k, v)| (plan::secret_placeholder(k), v.clone()))
+                .collect_vec(),
+            tee: self.tee,
+        });
+        let mut named = plan.resources.clone();
+        named.extend(self.resources.clone());
+        let mut budget = Budget {
+            cpu: self.workers.max(1) as u64,
+            memory: self.memory,
+            named,
+            locks: HashMap::new(),
+            slots: HashMap::new(),
+            lock_dir: self.lock_dir.clone(),
+            running: 0,
+            exclusive: false,
+        };
+
+        for (node_name, node) in plan.nodes.iter().sorted_by_key(|v| v.0) {
+            let named = node.resources.iter().flat_map(|v| v.named.keys());
+            if let Some(name) = named.sorted().find(|v| !budget.named.contains_key(*v)) {
+                Err(Error::Argument(format!(
+                    "node {} demands the resource {} which has no capacity",
+                    node_name, name
+                )))?
+            }
+            // non-script tasks always run locally, apart from the scripts they prepare or
+            // depend on
+            let executor_name = node.executor.as_deref().unwrap_or(&self.default_executor);
+            let remote = self
+                .executors
+                .get(executor_name)
+                .map(|v| v.is_remote())
+                .unwrap_or(false);
+            if remote && node.tasks.iter().any(|v| !matches!(v.action, plan::Action::Script(_))) {
+                Err(Error::InvalidTask(format!(
+                    "node {} runs on the remote executor {} and can only contain script tasks",
+                    node_name, executor_name
+                )))?
+            }
         }
 
         for stage in &plan.stages {
-            let pool = ThreadPool::new(workers);
-            let (signal_tx, signal_rx) = std::sync::mpsc::channel::<Result<()>>();
-            let mut signal_cnt = 0;
+            // the tasks of an invocation run in order, only invocations run in parallel
+            let mut pending = VecDeque::<(Demand, Vec<(TaskRecord, Work)>)>::new();
 
-            let nodes = stage.nodes.iter().map(|v| plan.nodes.get(v).unwrap());
-            for node in nodes {
+            let nodes = stage.nodes.iter().map(|v| (v, plan.nodes.get(v).unwrap()));
+            for (node_name, node) in nodes {
+                let executor_name = node.executor.as_deref().unwrap_or(&self.default_executor);
+                let executor = match self.executors.get(executor_name) {
+                    | Some(v) => v.clone(),
+                    | None => Err(Error::NotFound(format!("executor {}", executor_name)))?,
+                };
+                let mut demand = budget.demand(node.resources.as_ref());
+                demand.lock = node.lock.clone();
+                demand.slot = node.parallelism.map(|v| (node_name.clone(), v));
+                demand.exclusive = node.tasks.iter().any(|v| v.interactive);
+                let memory_limit = match &node.resources {
+                    | Some(v) if v.limit_memory => v.memory,
+                    | _ => None,
+                };
                 for matrix in &node.invocations {
-                    let mut work = Vec::<Work>::new();
-                    for task in &node.tasks {
+                    let mut works = Vec::<(TaskRecord, Work)>::new();
+                    for (task_idx, task) in node.tasks.iter().enumerate() {
                         let workdir = if let Some(workdir) = &task.workdir {
                             Some(workdir.to_owned())
                         } else if let Some(workdir) = &node.workdir {
@@ -63,67 +776,120 @@ impl ExecutionEngine {
                             }
                         };
 
-                        let mut env = plan.env.clone();
+                        let mut env = secrets.clone();
+                        env.extend(plan.env.clone());
                         env.extend(node.env.clone());
                         env.extend(matrix.env.clone());
                         env.extend(task.env.clone());
 
-                        signal_cnt += 1;
-                        work.push(Work {
-                            command: task.cmd.clone(),
-                            env,
-                            shell,
-                            workdir,
-                        })
+                        works.push((
+                            TaskRecord {
+                                node: node_name.clone(),
+                                coords: matrix.coords.clone(),
+                                task: task_idx,
+                                duration: 0,
+                                exit_code: None,
+                            },
+                            Work {
+                                executor: executor.clone(),
+                                sandbox: node.sandbox.clone(),
+                                memory_limit,
+                                action: task.action.clone(),
+                                env,
+                                shell,
+                                workdir,
+                                interactive: task.interactive,
+                                stdin: task.stdin.clone(),
+                                log: self.log_dir.as_ref().map(|v| {
+                                    v.join(path_component(node_name))
+                                        .join(path_component(&matrix.coords))
+                                        .join(format!("{}.log", task_idx))
+                                }),
+                            },
+                        ));
                     }
+                    pending.push_back((demand.clone(), works));
+                }
+            }
 
-                    let output = self.output.clone();
-                    // executes matrix entry
-                    for w in work {
-                        let t_tx = signal_tx.clone();
-                        pool.execute(move || {
-                            let res = move || -> Result<()> {
-                                let mut cmd_proc = std::process::Command::new(w.shell.program);
-                                cmd_proc.args(w.shell.args);
-                                cmd_proc.envs(w.env);
-                                if let Some(w) = w.workdir {
-                                    cmd_proc.current_dir(w);
-                                }
-                                cmd_proc.arg(&w.command);
-                                cmd_proc.stdin(Stdio::null());
-
-                                if !output.stdout {
-                                    cmd_proc.stdout(Stdio::null());
-                                }
-                                if !output.stderr {
-                                    cmd_proc.stderr(Stdio::null());
-                                }
-
-                                let output = cmd_proc.spawn()?.wait_with_output()?;
-
-                                match output.status.code().unwrap() {
-                                    | 0 => Ok(()),
-                                    | v => {
-                                        Err(Error::ChildProcess(format!(
-                                            "command: {} failed to execute with code {}",
-                                            w.command, v
-                                        )))
-                                    },
-                                }?;
-                                Ok(())
-                            }();
-                            t_tx.send(res).expect("send failed");
-                        });
+            let (signal_tx, signal_rx) = std::sync::mpsc::channel::<(Demand, Vec<TaskRecord>, Result<()>)>();
+            let mut running = 0;
+            let mut errs = Vec::<anyhow::Error>::new();
+            loop {
+                // starts every pending invocation (in order) whose resources are available
+                let mut idx = 0;
+                while idx < pending.len() {
+                    match budget.try_take(&pending[idx].0) {
+                        | Ok(true) => {},
+                        // later invocations must not keep an exclusive one from starting
+                        | Ok(false) if pending[idx].0.exclusive => break,
+                        | Ok(false) => {
+                            idx += 1;
+                            continue;
+                        },
+                        | Err(e) => {
+                            pending.remove(idx);
+                            errs.push(e);
+                            continue;
+                        },
                     }
+                    let (demand, works) = pending.remove(idx).unwrap();
+                    running += 1;
+                    let t_tx = signal_tx.clone();
+                    let ctx = ctx.clone();
+                    std::thread::spawn(move || {
+                        let mut done = Vec::<TaskRecord>::new();
+                        let mut res = Ok(());
+                        for (mut record, w) in works {
+                            let started = Instant::now();
+                            let run = w.run(&ctx);
+                            record.duration = started.elapsed().as_millis() as u64;
+                            res = match run {
+                                | Ok(Some(0)) => {
+                                    record.exit_code = Some(0);
+                                    Ok(())
+                                },
+                                | Ok(code) => {
+                                    record.exit_code = code;
+                                    Err(Error::ChildProcess(format!(
+                                        "command: {} failed to execute with code {}{}",
+                                        w.action,
+                                        code.map(|v| v.to_string()).unwrap_or("none".to_owned()),
+                                        match (&w.log, w.interactive) {
+                                            | (Some(v), false) => format!(" (log: {})", v.to_string_lossy()),
+                                            | _ => String::new(),
+                                        }
+                                    ))
+                                    .into())
+                                },
+                                | Err(e) => Err(e),
+                            };
+                            done.push(record);
+                            // the remaining tasks of the invocation depend on the failed one
+                            if res.is_err() {
+                                break;
+                            }
+                        }
+                        t_tx.send((demand, done, res)).expect("send failed");
+                    });
+                }
+                if running == 0 && pending.is_empty() {
+                    break;
                 }
-            }
 
-            let errs = signal_rx
-                .iter()
-                .take(signal_cnt)
-                .filter(|x| x.is_err())
-                .map(|x| x.expect_err("expecting an err"))
-                .collect::<Vec<_>>();
+                // lock files held by other processes are released without notice, so the
+                // pending invocations are retried periodically
+                let (demand, done, res) = match signal_rx.recv_timeout(LOCK_RETRY) {
+                    | Ok(v) => v,
+                    | Err(_) => continue,
+                };
+                running -= 1;
+                budget.give(&demand);
+                records.extend(done);
+                if let Err(e) = res {
+                    errs.push(e);
+                }
+            }
             if errs.len() > 0 {
                 return Err(Error::Many(errs).into());
                 // abort at this stage
@@ -132,3 +898,398 @@ impl ExecutionEngine {
         Ok(())
     }
 }
+
+/// How often invocations waiting for a lock file are retried.
+const LOCK_RETRY: Duration = Duration::from_millis(100);
+
+/// The share of the capacities an invocation occupies while its tasks run.
+#[derive(Debug, Clone)]
+struct Demand {
+    cpu: u64,
+    memory: u64,
+    named: Vec<(String, u64)>,
+    /// The lock that is held while the invocation runs.
+    lock: Option<String>,
+    /// The node and the number of its invocations that may run at once.
+    slot: Option<(String, usize)>,
+    /// Whether no other invocation may run at the same time.
+    exclusive: bool,
+}
+
+/// The capacities that are not occupied by running invocations.
+struct Budget {
+    cpu: u64,
+    memory: Option<u64>,
+    named: HashMap<String, u64>,
+    /// The held locks with the acquired lock file if the lock applies across
+    /// processes.
+    locks: HashMap<String, Option<std::fs::File>>,
+    /// The number of running invocations per node.
+    slots: HashMap<String, usize>,
+    /// Where the lock files are created, locks are process local if not set.
+    lock_dir: Option<PathBuf>,
+    /// The number of running invocations.
+    running: usize,
+    /// Whether an exclusive invocation is running.
+    exclusive: bool,
+}
+
+impl Budget {
+    /// Caps the resources of a node to the capacities so that every task can
+    /// run at least on its own.
+    fn demand(&self, resources: Option<&plan::Resources>) -> Demand {
+        let resources = match resources {
+            | Some(v) => v,
+            | None => {
+                return Demand {
+                    cpu: 1.min(self.cpu),
+                    memory: 0,
+                    named: vec![],
+                    lock: None,
+                    slot: None,
+                    exclusive: false,
+                }
+            },
+        };
+        Demand {
+            cpu: resources.cpu.min(self.cpu),
+            memory: match (resources.memory, self.memory) {
+                | (Some(v), Some(capacity)) => v.min(capacity),
+                | _ => 0,
+            },
+            named: resources
+                .named
+                .iter()
+                .map(|(k, v)| (k.clone(), (*v).min(self.named.get(k).copied().unwrap_or(0))))
+                .collect_vec(),
+            lock: None,
+            slot: None,
+            exclusive: false,
+        }
+    }
+
+    fn fits(&self, demand: &Demand) -> bool {
+        demand.cpu <= self.cpu
+            && self.memory.map(|v| demand.memory <= v).unwrap_or(true)
+            && demand
+                .named
+                .iter()
+                .all(|(k, v)| *v <= self.named.get(k).copied().unwrap_or(0))
+    }
+
+    /// Occupies the resources and acquires the lock of the demand if all of
+    /// them are available.
+    fn try_take(&mut self, demand: &Demand) -> Result<bool> {
+        if self.exclusive || (demand.exclusive && self.running > 0) || !self.fits(demand) {
+            return Ok(false);
+        }
+        if let Some((node, parallelism)) = &demand.slot {
+            if self.slots.get(node).copied().unwrap_or(0) >= *parallelism {
+                return Ok(false);
+            }
+        }
+        if let Some(lock) = &demand.lock {
+            if self.locks.contains_key(lock) {
+                return Ok(false);
+            }
+            let file = match &self.lock_dir {
+                | Some(dir) => {
+                    match lock_file(dir, lock)? {
+                        | Some(v) => Some(v),
+                        | None => return Ok(false),
+                    }
+                },
+                | None => None,
+            };
+            self.locks.insert(lock.clone(), file);
+        }
+        if let Some((node, _)) = &demand.slot {
+            *self.slots.entry(node.clone()).or_default() += 1;
+        }
+        self.take(demand);
+        Ok(true)
+    }
+
+    fn take(&mut self, demand: &Demand) {
+        self.running += 1;
+        self.exclusive = demand.exclusive;
+        self.cpu -= demand.cpu;
+        if let Some(memory) = &mut self.memory {
+            *memory -= demand.memory;
+        }
+        for (k, v) in &demand.named {
+            *self.named.get_mut(k).unwrap() -= v;
+        }
+    }
+
+    fn give(&mut self, demand: &Demand) {
+        self.running -= 1;
+        self.exclusive = false;
+        self.cpu += demand.cpu;
+        if let Some(memory) = &mut self.memory {
+            *memory += demand.memory;
+        }
+        for (k, v) in &demand.named {
+            *self.named.get_mut(k).unwrap() += v;
+        }
+        if let Some(lock) = &demand.lock {
+            // closing the lock file releases it
+            drop(self.locks.remove(lock));
+        }
+        if let Some((node, _)) = &demand.slot {
+            *self.slots.get_mut(node).unwrap() -= 1;
+        }
+    }
+}
+
+/// Acquires the lock file for the named lock without blocking. Returns `None`
+/// if another process holds it.
+fn lock_file(dir: &Path, name: &str) -> Result<Option<std::fs::File>> {
+    std::fs::create_dir_all(dir)?;
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(format!("{}.lock", name)))?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(Some(file));
+    }
+    match std::io::Error::last_os_error() {
+        | e if e.kind() == std::io::ErrorKind::WouldBlock => Ok(None),
+        | e => Err(e)?,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forward_masked_replaces_secrets_per_line() {
+        let mut out = Vec::<u8>::new();
+        let input = "token abc\nabcabc and def\nplain\nno newline def";
+        forward_masked(input.as_bytes(), &mut out, &["abc".to_owned(), "def".to_owned()]).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "token ***\n****** and ***\nplain\nno newline ***"
+        );
+    }
+
+    #[test]
+    fn forward_masked_keeps_invalid_utf8_lines() {
+        let mut out = Vec::<u8>::new();
+        forward_masked(&b"a\xffb secret\n"[..], &mut out, &["secret".to_owned()]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "a\u{fffd}b ***\n");
+    }
+
+    #[test]
+    fn path_components_are_encoded() {
+        assert_eq!(path_component("build"), "build");
+        assert_eq!(path_component("0,1"), "0,1");
+        assert_eq!(path_component("../a/b"), "%2E%2E%2Fa%2Fb");
+        assert_eq!(path_component("a%2F"), "a%252F");
+        assert_eq!(path_component("ä b"), "%C3%A4%20b");
+        assert_ne!(path_component("a/b"), path_component("a%2Fb"));
+    }
+
+    #[test]
+    fn reveal_replaces_secret_placeholders() {
+        let ctx = Context {
+            output: OutputMode::default(),
+            masks: vec![],
+            cancellation: None,
+            sink: None,
+            placeholders: vec![(plan::secret_placeholder("args.token"), "t0k".to_owned())],
+            tee: false,
+        };
+        let action = plan::Action::Write {
+            path: "out/{{secret:args.token}}".to_owned(),
+            content: "token={{secret:args.token}} other={{secret:args.other}}".to_owned(),
+        };
+        match ctx.reveal(&action) {
+            | plan::Action::Write { path, content } => {
+                assert_eq!(path, "out/t0k");
+                assert_eq!(content, "token=t0k other={{secret:args.other}}");
+            },
+            | v => panic!("unexpected action {:?}", v),
+        }
+    }
+
+    fn budget(cpu: u64, memory: Option<u64>, named: &[(&str, u64)]) -> Budget {
+        Budget {
+            cpu,
+            memory,
+            named: named.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+            locks: HashMap::new(),
+            slots: HashMap::new(),
+            lock_dir: None,
+            running: 0,
+            exclusive: false,
+        }
+    }
+
+    fn resources(cpu: u64, memory: Option<u64>, named: &[(&str, u64)]) -> plan::Resources {
+        plan::Resources {
+            cpu,
+            memory,
+            limit_memory: false,
+            named: named.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+        }
+    }
+
+    #[test]
+    fn budget_caps_demands_to_the_capacities() {
+        let budget = budget(4, Some(1024), &[("db", 1)]);
+        let demand = budget.demand(Some(&resources(8, Some(4096), &[("db", 2)])));
+        assert_eq!(demand.cpu, 4);
+        assert_eq!(demand.memory, 1024);
+        assert_eq!(demand.named, vec![("db".to_owned(), 1)]);
+        // memory is ignored without a capacity
+        assert_eq!(
+            self::budget(1, None, &[])
+                .demand(Some(&resources(1, Some(4096), &[])))
+                .memory,
+            0
+        );
+    }
+
+    #[test]
+    fn budget_takes_and_gives_back_resources() {
+        let mut budget = budget(2, Some(1024), &[("db", 1)]);
+        let small = budget.demand(None);
+        let db = budget.demand(Some(&resources(1, Some(512), &[("db", 1)])));
+        assert!(budget.try_take(&db).unwrap());
+        assert!(!budget.try_take(&db).unwrap());
+        assert!(budget.try_take(&small).unwrap());
+        assert!(!budget.try_take(&small).unwrap());
+        budget.give(&db);
+        assert_eq!((budget.cpu, budget.memory, budget.named["db"]), (1, Some(1024), 1));
+        assert!(budget.try_take(&db).unwrap());
+    }
+
+    #[test]
+    fn exclusive_demands_run_alone() {
+        let mut budget = budget(4, None, &[]);
+        let shared = budget.demand(None);
+        let mut exclusive = budget.demand(None);
+        exclusive.exclusive = true;
+        assert!(budget.try_take(&shared).unwrap());
+        assert!(!budget.try_take(&exclusive).unwrap());
+        budget.give(&shared);
+        assert!(budget.try_take(&exclusive).unwrap());
+        assert!(!budget.try_take(&shared).unwrap());
+        budget.give(&exclusive);
+        assert!(budget.try_take(&shared).unwrap());
+    }
+
+    #[test]
+    fn locks_are_held_by_one_invocation() {
+        let mut budget = budget(4, None, &[]);
+        let mut demand = budget.demand(None);
+        demand.lock = Some("db".to_owned());
+        assert!(budget.try_take(&demand).unwrap());
+        assert!(!budget.try_take(&demand).unwrap());
+        budget.give(&demand);
+        assert!(budget.try_take(&demand).unwrap());
+    }
+
+    #[test]
+    fn lock_files_apply_across_budgets() {
+        let dir = std::env::temp_dir().join(format!("neomake-locks-{}", std::process::id()));
+        let mut a = budget(1, None, &[]);
+        let mut b = budget(1, None, &[]);
+        a.lock_dir = Some(dir.clone());
+        b.lock_dir = Some(dir.clone());
+        let mut demand = a.demand(None);
+        demand.lock = Some("db".to_owned());
+        assert!(a.try_take(&demand).unwrap());
+        // the other budget stands for another process using the same directory
+        assert!(!b.try_take(&demand).unwrap());
+        assert_eq!(b.cpu, 1);
+        a.give(&demand);
+        assert!(b.try_take(&demand).unwrap());
+        b.give(&demand);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn slots_limit_the_invocations_of_a_node() {
+        let mut budget = budget(4, None, &[]);
+        let mut demand = budget.demand(None);
+        demand.slot = Some(("test".to_owned(), 2));
+        let mut other = budget.demand(None);
+        other.slot = Some(("other".to_owned(), 1));
+        assert!(budget.try_take(&demand).unwrap());
+        assert!(budget.try_take(&demand).unwrap());
+        assert!(!budget.try_take(&demand).unwrap());
+        assert!(budget.try_take(&other).unwrap());
+        budget.give(&demand);
+        assert!(budget.try_take(&demand).unwrap());
+    }
+
+    #[test]
+    fn unknown_resources_are_rejected() {
+        let mut plan = crate::Compiler::new(
+            crate::Workflow::load(
+                r#"
+version: "0.0"
+resources:
+  db: 1
+nodes:
+  migrate:
+    resources:
+      db: 1
+    tasks:
+      - script: "true"
+"#,
+            )
+            .unwrap(),
+        )
+        .plan(
+            &std::collections::HashSet::from(["migrate".to_owned()]),
+            &HashMap::new(),
+        )
+        .unwrap();
+        plan.resources.clear();
+        match ExecutionEngine::default()
+            .execute(&plan)
+            .unwrap_err()
+            .downcast::<Error>()
+        {
+            | Ok(Error::Argument(v)) => assert_eq!(v, "node migrate demands the resource db which has no capacity"),
+            | v => panic!("unexpected result {:?}", v),
+        }
+    }
+
+    #[test]
+    fn history_failures_are_returned_with_the_result() {
+        let plan = crate::Compiler::new(
+            crate::Workflow::load(
+                r#"
+version: "0.0"
+nodes:
+  a:
+    tasks:
+      - script: "true"
+"#,
+            )
+            .unwrap(),
+        )
+        .plan(&std::collections::HashSet::from(["a".to_owned()]), &HashMap::new())
+        .unwrap();
+        // a file where the history directory is expected
+        let file = std::env::temp_dir().join(format!("neomake-exec-history-{}", std::process::id()));
+        std::fs::write(&file, "").unwrap();
+        let recorded = ExecutionEngine::default()
+            .with_output(OutputMode {
+                stdout: false,
+                stderr: false,
+            })
+            .with_history(History::new(&file))
+            .execute_recorded(&plan, None);
+        std::fs::remove_file(&file).unwrap();
+        assert!(recorded.result.is_ok());
+        assert!(recorded.history.is_some());
+        assert_eq!(recorded.tasks.len(), 1);
+    }
+}
@@ -0,0 +1,284 @@
+use {
+    crate::plan,
+    anyhow::Result,
+    std::{
+        collections::HashMap,
+        io::{
+            Read,
+            Write,
+        },
+        os::unix::process::CommandExt,
+        process::Stdio,
+        sync::{
+            Arc,
+            Mutex,
+        },
+        time::Duration,
+    },
+};
+
+/// The name of the executor that is used when a node does not set one.
+pub const LOCAL: &str = "local";
+
+/// A script task with everything resolved that is needed to run it.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub struct ResolvedTask {
+    pub shell: plan::Shell,
+    /// The script, passed as the last arg to the shell.
+    pub command: String,
+    /// The complete environment (plan, node, matrix and task env vars as well
+    /// as the secrets).
+    pub env: HashMap<String, String>,
+    pub workdir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<plan::Sandbox>,
+    /// The maximum size of the address space of the script in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_limit: Option<u64>,
+    /// Whether the script reads from the terminal (`/dev/tty`) and inherits
+    /// stdout and stderr.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub interactive: bool,
+    /// Written to the stdin of the script, which is empty if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin: Option<Vec<u8>>,
+}
+
+/// What to do with an output stream of a task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    /// Discard the output.
+    Null,
+    /// Write the output to the terminal directly. Executors that cannot do
+    /// so return the stream instead.
+    Inherit,
+    /// Return the output as a stream.
+    Piped,
+}
+
+/// How a task is to be spawned.
+#[derive(Debug, Clone, Copy)]
+pub struct Spawn {
+    pub stdout: Output,
+    pub stderr: Output,
+    /// Whether the task may be terminated before it exits, see
+    /// [`Process::terminator`].
+    pub terminable: bool,
+}
+
+/// How forcefully a task is terminated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Termination {
+    /// Asks the task to exit (SIGTERM).
+    Graceful,
+    /// Kills the task (SIGKILL), for tasks that ignore a graceful termination.
+    Forced,
+}
+
+/// How long a task is given to exit after a graceful termination before it is
+/// killed.
+pub const TERMINATION_GRACE: Duration = Duration::from_secs(5);
+
+/// Terminates a running task including everything it started. Does nothing
+/// once the task exited.
+pub type Terminator = Box<dyn Fn(Termination)+Send+Sync>;
+
+/// A task that has been spawned by an [`Executor`].
+pub trait Process: Send {
+    /// Takes the stdout stream. Returns `None` if the output is not piped or
+    /// was already taken.
+    fn stdout(&mut self) -> Option<Box<dyn Read+Send>>;
+    /// Takes the stderr stream. Returns `None` if the output is not piped or
+    /// was already taken.
+    fn stderr(&mut self) -> Option<Box<dyn Read+Send>>;
+    /// Waits for the task to exit and returns its exit code (`None` if it was
+    /// terminated by a signal).
+    fn wait(&mut self) -> Result<Option<i32>>;
+    /// Returns a function that terminates the task. Only called for tasks that
+    /// were spawned as terminable.
+    fn terminator(&self) -> Terminator;
+}
+
+/// Runs script tasks. Implementations decide where and how a task is run, the
+/// engine takes care of scheduling, output forwarding and cancellation.
+pub trait Executor: Send+Sync {
+    fn spawn(&self, task: &ResolvedTask, spawn: Spawn) -> Result<Box<dyn Process>>;
+
+    /// Whether the tasks run on another machine. All other task kinds only run
+    /// locally, so nodes mixing them with scripts are rejected for remote
+    /// executors.
+    fn is_remote(&self) -> bool {
+        false
+    }
+}
+
+/// Runs tasks as child processes of the current process.
+#[derive(Debug, Clone, Default)]
+pub struct LocalExecutor;
+
+impl LocalExecutor {
+    /// Builds the command for the task without spawning it, for executors that
+    /// run tasks locally with additional isolation.
+    pub fn command(task: &ResolvedTask, spawn: Spawn) -> Result<std::process::Command> {
+        let mut cmd_proc = std::process::Command::new(&task.shell.program);
+        cmd_proc.args(&task.shell.args);
+        cmd_proc.envs(&task.env);
+        if let Some(w) = &task.workdir {
+            cmd_proc.current_dir(w);
+        }
+        cmd_proc.arg(&task.command);
+        cmd_proc.stdin(match (task.interactive, &task.stdin) {
+            // the stdin of the current process might be the plan
+            | (true, _) => {
+                match std::fs::File::open("/dev/tty") {
+                    | Ok(v) => Stdio::from(v),
+                    | Err(e) => {
+                        Err(crate::error::Error::InvalidTask(format!(
+                            "interactive scripts require a terminal: {}",
+                            e
+                        )))?
+                    },
+                }
+            },
+            | (false, Some(_)) => Stdio::piped(),
+            | (false, None) => Stdio::null(),
+        });
+
+        // interactive scripts stay in the foreground process group to be able to
+        // read from the terminal
+        if spawn.terminable && !task.interactive {
+            // own process group so that the entire process tree can be terminated
+            cmd_proc.process_group(0);
+        }
+
+        let stdio = |v: Output| {
+            match v {
+                | Output::Null => Stdio::null(),
+                | Output::Inherit => Stdio::inherit(),
+                | Output::Piped => Stdio::piped(),
+            }
+        };
+        cmd_proc.stdout(stdio(spawn.stdout));
+        cmd_proc.stderr(stdio(spawn.stderr));
+
+        if let Some(limit) = task.memory_limit {
+            unsafe {
+                cmd_proc.pre_exec(move || {
+                    let rlimit = libc::rlimit {
+                        rlim_cur: limit as libc::rlim_t,
+                        rlim_max: limit as libc::rlim_t,
+                    };
+                    match libc::setrlimit(libc::RLIMIT_AS, &rlimit) {
+                        | -1 => Err(std::io::Error::last_os_error()),
+                        | _ => Ok(()),
+                    }
+                });
+            }
+        }
+        if let Some(sandbox) = &task.sandbox {
+            #[cfg(target_os = "linux")]
+            {
+                let prepared = crate::sandbox::Prepared::new(sandbox, task.workdir.as_deref())?;
+                unsafe {
+                    cmd_proc.pre_exec(move || prepared.enter());
+                }
+            }
+            #[cfg(not(target_os = "linux"))]
+            {
+                let _ = sandbox;
+                Err(crate::error::Error::Argument(
+                    "sandboxes are only supported on Linux".to_owned(),
+                ))?
+            }
+        }
+        Ok(cmd_proc)
+    }
+}
+
+impl Executor for LocalExecutor {
+    fn spawn(&self, task: &ResolvedTask, spawn: Spawn) -> Result<Box<dyn Process>> {
+        let mut child = Self::command(task, spawn)?.spawn()?;
+        if let (Some(mut to), Some(content)) = (child.stdin.take(), task.stdin.clone()) {
+            // scripts that exit without reading their stdin close the pipe
+            std::thread::spawn(move || {
+                let _ = to.write_all(&content);
+            });
+        }
+        Ok(Box::new(LocalProcess::new(child)))
+    }
+}
+
+/// A child process spawned by the [`LocalExecutor`].
+pub struct LocalProcess {
+    child: std::process::Child,
+    /// Set once the child has been reaped. Its pid (and process group) may be
+    /// reused by another process from then on, so it must not be signalled
+    /// anymore.
+    reaped: Arc<Mutex<bool>>,
+}
+
+impl LocalProcess {
+    pub fn new(child: std::process::Child) -> Self {
+        Self {
+            child,
+            reaped: Arc::new(Mutex::new(false)),
+        }
+    }
+}
+
+impl Process for LocalProcess {
+    fn stdout(&mut self) -> Option<Box<dyn Read+Send>> {
+        self.child.stdout.take().map(|v| Box::new(v) as Box<dyn Read+Send>)
+    }
+
+    fn stderr(&mut self) -> Option<Box<dyn Read+Send>> {
+        self.child.stderr.take().map(|v| Box::new(v) as Box<dyn Read+Send>)
+    }
+
+    fn wait(&mut self) -> Result<Option<i32>> {
+        // waits for the exit without reaping the child so that the terminator can not
+        // signal a reused pid
+        loop {
+            let mut info = unsafe { std::mem::zeroed::<libc::siginfo_t>() };
+            let res = unsafe {
+                libc::waitid(
+                    libc::P_PID,
+                    self.child.id() as libc::id_t,
+                    &mut info,
+                    libc::WEXITED | libc::WNOWAIT,
+                )
+            };
+            match std::io::Error::last_os_error() {
+                | _ if res == 0 => break,
+                | e if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                | e => Err(e)?,
+            }
+        }
+        let mut reaped = self.reaped.lock().unwrap();
+        let status = self.child.wait()?;
+        *reaped = true;
+        Ok(status.code())
+    }
+
+    fn terminator(&self) -> Terminator {
+        let group = self.child.id() as libc::pid_t;
+        let reaped = self.reaped.clone();
+        Box::new(move |termination| {
+            let reaped = reaped.lock().unwrap();
+            if *reaped {
+                return;
+            }
+            let signal = match termination {
+                | Termination::Graceful => libc::SIGTERM,
+                | Termination::Forced => libc::SIGKILL,
+            };
+            unsafe {
+                // interactive scripts do not have their own process group
+                if libc::killpg(group, signal) == -1 {
+                    libc::kill(group, signal);
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,88 @@
+use {
+    crate::error::Error,
+    anyhow::Result,
+};
+
+/// Serialization format for plans and command outputs. Formats other than
+/// YAML are enabled through the `format+*` features.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    YAML,
+    #[cfg(feature = "format+json")]
+    JSON {
+        pretty: bool,
+    },
+    #[cfg(feature = "format+toml")]
+    TOML,
+    #[cfg(feature = "format+ron")]
+    RON {
+        pretty: bool,
+    },
+}
+
+impl Format {
+    pub fn serialize<T: serde::Serialize>(&self, arg: &T) -> Result<String> {
+        match self {
+            | Self::YAML => Ok(serde_yaml::to_string(arg)?),
+            #[cfg(feature = "format+json")]
+            | Self::JSON { pretty } => {
+                if *pretty {
+                    Ok(serde_json::to_string_pretty(arg)?)
+                } else {
+                    Ok(serde_json::to_string(arg)?)
+                }
+            },
+            #[cfg(feature = "format+toml")]
+            | Self::TOML => Ok(toml::to_string(arg)?),
+            #[cfg(feature = "format+ron")]
+            | Self::RON { pretty } => {
+                if *pretty {
+                    Ok(ron::ser::to_string_pretty(
+                        arg,
+                        ron::ser::PrettyConfig::new()
+                            .compact_arrays(true)
+                            .enumerate_arrays(true)
+                            .new_line("\n".to_owned()), // no windows on my turf
+                    )?)
+                } else {
+                    Ok(ron::ser::to_string(arg)?)
+                }
+            },
+        }
+    }
+
+    pub fn deserialize<T: serde::de::DeserializeOwned>(&self, s: &str) -> Result<T> {
+        match self {
+            | Self::YAML => Ok(serde_yaml::from_str::<T>(s)?),
+            #[cfg(feature = "format+json")]
+            | Self::JSON { .. } => Ok(serde_json::from_str::<T>(s)?),
+            #[cfg(feature = "format+toml")]
+            | Self::TOML => Ok(toml::from_str::<T>(s)?),
+            #[cfg(feature = "format+ron")]
+            | Self::RON { .. } => Ok(ron::from_str::<T>(s)?),
+        }
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = anyhow::Error;
+
+    /// Parses the format from its name as used on the command line (like
+    /// `yaml` or `json+p`).
+    fn from_str(arg: &str) -> Result<Self> {
+        match arg {
+            | "yaml" => Ok(Format::YAML),
+            #[cfg(feature = "format+json")]
+            | "json" => Ok(Format::JSON { pretty: false }),
+            #[cfg(feature = "format+json")]
+            | "json+p" => Ok(Format::JSON { pretty: true }),
+            #[cfg(feature = "format+toml")]
+            | "toml" => Ok(Format::TOML),
+            #[cfg(feature = "format+ron")]
+            | "ron" => Ok(Format::RON { pretty: false }),
+            #[cfg(feature = "format+ron")]
+            | "ron+p" => Ok(Format::RON { pretty: true }),
+            | _ => Err(Error::Argument("output".to_owned()).into()),
+        }
+    }
+}
@@ -0,0 +1,332 @@
+use {
+    crate::{
+        error::Error,
+        plan::ExecutionPlan,
+    },
+    anyhow::Result,
+    itertools::Itertools,
+    std::{
+        collections::{
+            BTreeMap,
+            HashMap,
+        },
+        path::{
+            Path,
+            PathBuf,
+        },
+        time::{
+            Duration,
+            SystemTime,
+            UNIX_EPOCH,
+        },
+    },
+};
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+/// A single recorded run.
+pub struct Record {
+    pub id: String,
+    /// Unix timestamp (seconds) of the start of the run.
+    pub timestamp: u64,
+    /// Hash of the executed plan.
+    pub plan: String,
+    pub nodes: Vec<String>,
+    pub args: HashMap<String, String>,
+    /// Duration in milliseconds.
+    pub duration: u64,
+    pub success: bool,
+    pub tasks: Vec<TaskRecord>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+/// The outcome of a single task in a single matrix invocation.
+pub struct TaskRecord {
+    pub node: String,
+    pub coords: String,
+    pub task: usize,
+    /// Duration in milliseconds.
+    pub duration: u64,
+    /// The exit code. Not set if the task did not finish with one (like when
+    /// killed by a signal).
+    pub exit_code: Option<i32>,
+}
+
+/// The records of a history, see [`History::load`].
+#[derive(Debug)]
+pub struct Loaded {
+    /// The records sorted from oldest to newest.
+    pub records: Vec<Record>,
+    /// Files that could not be read as a record.
+    pub skipped: Vec<Skipped>,
+}
+
+/// A file that could not be read as a record.
+#[derive(Debug)]
+pub struct Skipped {
+    pub path: PathBuf,
+    pub error: anyhow::Error,
+}
+
+/// Summaries of runs, see [`History::list`].
+#[derive(Debug, serde::Serialize)]
+pub struct Runs {
+    pub runs: Vec<Run>,
+    #[serde(skip)]
+    pub skipped: Vec<Skipped>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Run {
+    pub id: String,
+    pub timestamp: u64,
+    pub plan: String,
+    pub nodes: Vec<String>,
+    pub duration: u64,
+    pub success: bool,
+}
+
+/// Statistics of the nodes, see [`History::stats`].
+#[derive(Debug, serde::Serialize)]
+pub struct Stats {
+    #[serde(flatten)]
+    pub nodes: BTreeMap<String, NodeStats>,
+    #[serde(skip)]
+    pub skipped: Vec<Skipped>,
+}
+
+/// Statistics of a single node, see [`History::stats`].
+#[derive(Debug, Default, serde::Serialize)]
+pub struct NodeStats {
+    pub runs: usize,
+    pub failed: usize,
+    /// Whether the last run of this node failed.
+    pub last_failed: bool,
+    /// Summed task durations of the last run in milliseconds.
+    pub last_duration: u64,
+    /// Average of the summed task durations in milliseconds.
+    pub avg_duration: u64,
+}
+
+/// Stores one JSON file per run in a directory.
+#[derive(Debug, Clone)]
+pub struct History {
+    pub dir: PathBuf,
+}
+
+impl History {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn now() -> Duration {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+    }
+
+    /// Computes a hash of the plan that is independent of the ordering of its
+    /// maps (FNV-1a over the canonical JSON).
+    pub fn hash(plan: &ExecutionPlan) -> Result<String> {
+        let canonical = serde_json::to_string(&serde_json::to_value(plan)?)?;
+        let mut hash = 0xcbf29ce484222325u64;
+        for b in canonical.as_bytes() {
+            hash ^= *b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        Ok(format!("{:016x}", hash))
+    }
+
+    pub fn store(&self, record: &Record) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(
+            self.dir.join(format!("{}.json", record.id)),
+            serde_json::to_string_pretty(record)?,
+        )?;
+        Ok(())
+    }
+
+    /// Loads all records, sorted from oldest to newest. Files that can not be
+    /// read as a record are skipped and returned alongside.
+    pub fn load(&self) -> Result<Loaded> {
+        let mut records = Vec::<Record>::new();
+        let mut skipped = Vec::<Skipped>::new();
+        if !self.dir.exists() {
+            return Ok(Loaded { records, skipped });
+        }
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if !path.extension().map(|v| v == "json").unwrap_or(false) {
+                continue;
+            }
+            let record = std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|v| Ok(serde_json::from_str::<Record>(&v)?));
+            match record {
+                | Ok(v) => records.push(v),
+                | Err(error) => skipped.push(Skipped { path, error }),
+            }
+        }
+        records.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(Loaded { records, skipped })
+    }
+
+    /// Lists the most recent runs (all if no limit is given), oldest first.
+    pub fn list(&self, limit: Option<usize>) -> Result<Runs> {
+        let Loaded { records, skipped } = self.load()?;
+        let skip = match limit {
+            | Some(v) => records.len().saturating_sub(v),
+            | None => 0,
+        };
+        Ok(Runs {
+            runs: records
+                .into_iter()
+                .skip(skip)
+                .map(|r| {
+                    Run {
+                        id: r.id,
+                        timestamp: r.timestamp,
+                        plan: r.plan,
+                        nodes: r.nodes,
+                        duration: r.duration,
+                        success: r.success,
+                    }
+                })
+                .collect_vec(),
+            skipped,
+        })
+    }
+
+    /// Loads a single run including all of its tasks.
+    pub fn show(&self, id: &str) -> Result<Record> {
+        let Loaded { records, skipped } = self.load()?;
+        if let Some(v) = records.into_iter().find(|r| r.id == id) {
+            return Ok(v);
+        }
+        // the file of the run may exist but be unreadable
+        let file = format!("{}.json", id);
+        match skipped
+            .into_iter()
+            .find(|v| v.path.file_name() == Some(Path::new(&file).as_os_str()))
+        {
+            | Some(v) => Err(v.error),
+            | None => Err(Error::NotFound(format!("run {}", id)))?,
+        }
+    }
+
+    /// Aggregates the runs per node.
+    pub fn stats(&self) -> Result<Stats> {
+        let Loaded { records, skipped } = self.load()?;
+        let mut nodes = BTreeMap::<String, NodeStats>::new();
+        let mut totals = HashMap::<String, u64>::new();
+        for record in records {
+            let by_node = record.tasks.iter().into_group_map_by(|t| t.node.clone());
+            for (node, tasks) in by_node {
+                let duration = tasks.iter().map(|t| t.duration).sum::<u64>();
+                let failed = tasks.iter().any(|t| t.exit_code != Some(0));
+                let stats = nodes.entry(node.clone()).or_default();
+                stats.runs += 1;
+                if failed {
+                    stats.failed += 1;
+                }
+                stats.last_failed = failed;
+                stats.last_duration = duration;
+                *totals.entry(node).or_default() += duration;
+            }
+        }
+        for (node, stats) in nodes.iter_mut() {
+            stats.avg_duration = totals[node] / stats.runs as u64;
+        }
+        Ok(Stats { nodes, skipped })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(name: &str) -> History {
+        let dir = std::env::temp_dir().join(format!("neomake-history-{}-{}", name, std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        History::new(dir)
+    }
+
+    fn record(id: &str, tasks: &[(&str, u64, Option<i32>)]) -> Record {
+        Record {
+            id: id.to_owned(),
+            timestamp: 0,
+            plan: "0".to_owned(),
+            nodes: vec!["a".to_owned()],
+            args: HashMap::from([("args.x".to_owned(), "1".to_owned())]),
+            duration: 0,
+            success: tasks.iter().all(|t| t.2 == Some(0)),
+            tasks: tasks
+                .iter()
+                .map(|(node, duration, exit_code)| {
+                    TaskRecord {
+                        node: node.to_string(),
+                        coords: String::new(),
+                        task: 0,
+                        duration: *duration,
+                        exit_code: *exit_code,
+                    }
+                })
+                .collect_vec(),
+        }
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let history = history("round-trip");
+        history.store(&record("2", &[("a", 10, Some(0))])).unwrap();
+        history.store(&record("1", &[("a", 20, Some(1))])).unwrap();
+
+        let runs = history.list(None).unwrap().runs;
+        assert_eq!(runs.iter().map(|r| r.id.as_str()).collect_vec(), vec!["1", "2"]);
+        assert_eq!(history.list(Some(1)).unwrap().runs[0].id, "2");
+
+        let run = history.show("1").unwrap();
+        assert_eq!(run.args["args.x"], "1");
+        assert_eq!(run.tasks[0].exit_code, Some(1));
+        assert!(history.show("3").is_err());
+        std::fs::remove_dir_all(&history.dir).unwrap();
+    }
+
+    #[test]
+    fn stats_aggregate_per_node() {
+        let history = history("stats");
+        history
+            .store(&record("1", &[("a", 10, Some(0)), ("a", 20, Some(0)), ("b", 5, None)]))
+            .unwrap();
+        history.store(&record("2", &[("a", 50, Some(1))])).unwrap();
+
+        let stats = history.stats().unwrap().nodes;
+        assert_eq!(stats["a"].runs, 2);
+        assert_eq!(stats["a"].failed, 1);
+        assert!(stats["a"].last_failed);
+        assert_eq!(stats["a"].last_duration, 50);
+        assert_eq!(stats["a"].avg_duration, 40);
+        assert_eq!(stats["b"].runs, 1);
+        assert_eq!(stats["b"].failed, 1);
+        std::fs::remove_dir_all(&history.dir).unwrap();
+    }
+
+    #[test]
+    fn unreadable_records_are_skipped() {
+        let history = history("skip");
+        history.store(&record("1", &[("a", 10, Some(0))])).unwrap();
+        std::fs::write(history.dir.join("2.json"), "{").unwrap();
+        std::fs::write(history.dir.join("notes.txt"), "").unwrap();
+
+        let loaded = history.load().unwrap();
+        assert_eq!(loaded.records.len(), 1);
+        assert_eq!(loaded.skipped.len(), 1);
+        assert!(loaded.skipped[0].path.ends_with("2.json"));
+        assert_eq!(history.list(None).unwrap().skipped.len(), 1);
+        assert!(history
+            .show("2")
+            .unwrap_err()
+            .downcast_ref::<serde_json::Error>()
+            .is_some());
+        std::fs::remove_dir_all(&history.dir).unwrap();
+    }
+}
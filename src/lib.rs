@@ -0,0 +1,54 @@
+//! `neomake` is a task runner. This library contains everything to load
+//! workflows, plan and execute them and is what the `neomake` CLI is built on.
+//!
+//! A [`Workflow`] is loaded from its YAML definition and handed to a
+//! [`Compiler`], which renders the selected nodes (including their `pre`
+//! nodes) into an [`ExecutionPlan`]. The plan is serializable and can be
+//! executed by an [`ExecutionEngine`] right away or stored and executed later.
+//!
+//! ```no_run
+//! use {
+//!     neomake::{
+//!         Compiler,
+//!         ExecutionEngine,
+//!         Workflow,
+//!     },
+//!     std::collections::{
+//!         HashMap,
+//!         HashSet,
+//!     },
+//! };
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let workflow = Workflow::load(&std::fs::read_to_string(".neomake.yaml")?)?;
+//! let plan =
+//!     Compiler::new(workflow).plan(&HashSet::from(["build".to_owned()]), &HashMap::new())?;
+//! ExecutionEngine::default().with_workers(4).execute(&plan)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! All fallible functions return an [`anyhow::Error`]. Failures that are
+//! specific to `neomake` are represented by [`Error`] and can be retrieved via
+//! [`anyhow::Error::downcast_ref`].
+
+pub mod agent;
+pub mod compiler;
+pub mod error;
+pub mod exec;
+pub mod executor;
+pub mod format;
+pub mod history;
+pub mod plan;
+#[cfg(target_os = "linux")]
+mod sandbox;
+pub mod workflow;
+
+pub use {
+    compiler::Compiler,
+    error::Error,
+    exec::ExecutionEngine,
+    format::Format,
+    plan::ExecutionPlan,
+    workflow::Workflow,
+};
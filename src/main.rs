@@ -1,16 +1,5 @@
 use {
-    args::Nodes,
-    crossterm::{
-        cursor::MoveTo,
-        terminal::{
-            Clear,
-            ClearType,
-        },
-    },
-    notify::{
-        RecommendedWatcher,
-        Watcher,
-    },
+    itertools::Itertools,
     signal_hook::{
         consts::{
             SIGINT,
@@ -18,70 +7,53 @@ use {
         },
         iterator::Signals,
     },
-    std::{
-        cell::Cell,
-        collections::{
-            HashMap,
-            HashSet,
-        },
-        io::{
-            stdout,
-            BufWriter,
-            Write,
-        },
-        ops::Deref,
-        path::Path,
-        sync::{
-            Arc,
-            Mutex,
-            RwLock,
-        },
-        thread::sleep,
-        time::Duration,
-    },
-    tokio::{
-        process::Command,
-        task::{
-            yield_now,
-            JoinSet,
-        },
-    },
-    workflow::WatchExecStep,
+    std::sync::Arc,
 };
 
 include!("check_features.rs");
 
-pub mod args;
-pub mod compiler;
-pub mod error;
-pub mod exec;
-pub mod plan;
-pub mod reference;
-pub mod workflow;
+mod args;
+mod multiplex;
+mod reference;
+mod watch;
 
 use {
-    crate::{
-        compiler::Compiler,
-        workflow::Workflow,
-    },
     anyhow::Result,
     args::{
         InitOutput,
         ManualFormat,
     },
-    exec::{
+    multiplex::Multiplex,
+    neomake::{
+        agent::{
+            Agent,
+            RemoteExecutor,
+        },
+        compiler::NodeRef,
+        error::Error,
+        exec::OutputMode,
+        history::{
+            History,
+            Skipped,
+        },
+        workflow::{
+            WatchBackend,
+            WatchExec,
+            WatchExecStep,
+        },
+        Compiler,
         ExecutionEngine,
-        OutputMode,
+        Workflow,
     },
     std::path::PathBuf,
+    watch::Watch,
 };
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let cmd = crate::args::ClapArgumentLoader::load()?;
+fn main() -> Result<()> {
+    let cmd = args::ClapArgumentLoader::load()?;
 
     match cmd.command {
-        | crate::args::Command::Manual { path, format } => {
+        | args::Command::Manual { path, format } => {
             let out_path = PathBuf::from(path);
             std::fs::create_dir_all(&out_path)?;
             match format {
@@ -94,59 +66,106 @@ async fn main() -> Result<()> {
             }
             Ok(())
         },
-        | crate::args::Command::Autocomplete { path, shell } => {
+        | args::Command::Autocomplete { path, shell } => {
             let out_path = PathBuf::from(path);
             std::fs::create_dir_all(&out_path)?;
             reference::build_shell_completion(&out_path, &shell)?;
             Ok(())
         },
-        | crate::args::Command::WorkflowInit { template, output } => {
+        | args::Command::WorkflowInit { template, output } => {
             match output {
                 | InitOutput::File(f) => std::fs::write(f, template.render())?,
                 | InitOutput::Stdout => print!("{}", template.render()),
             };
             Ok(())
         },
-        | crate::args::Command::WorkflowSchema => {
+        | args::Command::WorkflowSchema => {
             print!(
                 "{}",
-                serde_json::to_string_pretty(&schemars::schema_for!(crate::workflow::Workflow)).unwrap()
+                serde_json::to_string_pretty(&schemars::schema_for!(Workflow)).unwrap()
             );
             Ok(())
         },
-        | crate::args::Command::Execute {
+        | args::Command::Execute {
             plan,
             workers,
+            secrets,
+            history,
             no_stdout,
             no_stderr,
+            memory,
+            resources,
+            lock_dir,
+            log_dir,
+            tee,
+            agents,
+            agent_token,
+        } => {
+            let mut exec_engine = ExecutionEngine::default()
+                .with_output(OutputMode {
+                    stdout: !no_stdout,
+                    stderr: !no_stderr,
+                })
+                .with_workers(workers)
+                .with_resources(resources)
+                .with_secrets(secrets);
+            if let Some(memory) = memory {
+                exec_engine = exec_engine.with_memory(memory);
+            }
+            if let Some(lock_dir) = lock_dir {
+                exec_engine = exec_engine.with_lock_dir(PathBuf::from(lock_dir));
+            }
+            if let Some(log_dir) = log_dir {
+                exec_engine = exec_engine.with_log_dir(PathBuf::from(log_dir)).with_tee(tee);
+            }
+            if let Some(history) = history {
+                exec_engine = exec_engine.with_history(History::new(history));
+            }
+            if !agents.is_empty() {
+                exec_engine = exec_engine
+                    .with_executor("agents", Arc::new(RemoteExecutor::new(agents, &agent_token)?))
+                    .with_default_executor("agents");
+            }
+            execute(&exec_engine, &plan)
+        },
+        | args::Command::Agent {
+            listen,
+            token,
+            allow_remote,
         } => {
-            let exec_engine = ExecutionEngine::new(OutputMode {
-                stdout: !no_stdout,
-                stderr: !no_stderr,
-            });
-            exec_engine.execute(&plan, workers)?;
+            let agent = Agent::bind(&listen, &token)?;
+            let addr = agent.local_addr()?;
+            if !addr.ip().is_loopback() && !allow_remote {
+                Err(Error::Argument(format!(
+                    "refusing to listen on the non-loopback address {} without --allow-remote",
+                    addr
+                )))?
+            }
+            eprintln!("listening on {}", addr);
+            agent.serve()?;
             Ok(())
         },
-        | crate::args::Command::Plan {
+        | args::Command::Plan {
             workflow,
             nodes,
             args,
+            secret_args,
             format,
         } => {
             let w = Workflow::load(&workflow)?;
             let nodes = nodes.select(&w)?;
-            let c = Compiler::new(w);
+            let c = Compiler::new(w).with_secret_args(secret_args);
             let x = c.plan(&nodes, &args)?;
             print!("{}", format.serialize(&x)?);
             Ok(())
         },
-        | crate::args::Command::List { workflow, format } => {
+        | args::Command::List { workflow, format } => {
             let w = Workflow::load(&workflow)?;
             let c = Compiler::new(w);
-            c.list(&format).await?;
+            println!("{}", format.serialize(&c.list())?);
             Ok(())
         },
-        | crate::args::Command::Describe {
+        | args::Command::Describe {
             workflow,
             nodes,
             format,
@@ -154,250 +173,166 @@ async fn main() -> Result<()> {
             let w = Workflow::load(&workflow)?;
             let nodes = nodes.select(&w)?;
             let c = Compiler::new(w);
-            c.describe(&nodes, &format).await?;
+            println!("{}", format.serialize(&c.describe(&nodes)?)?);
+            Ok(())
+        },
+        | args::Command::HistoryList { dir, limit, format } => {
+            let runs = History::new(dir).list(limit)?;
+            report_skipped(&runs.skipped);
+            println!("{}", format.serialize(&runs)?);
             Ok(())
         },
-        | crate::args::Command::Multiplex { commands } => {
-            let mut command_states = HashMap::<String, String>::new();
-            for command in commands.iter() {
-                command_states.insert(command.clone(), "PENDING".to_owned());
+        | args::Command::HistoryShow { dir, id, format } => {
+            println!("{}", format.serialize(&History::new(dir).show(&id)?)?);
+            Ok(())
+        },
+        | args::Command::HistoryStats { dir, format } => {
+            let stats = History::new(dir).stats()?;
+            report_skipped(&stats.skipped);
+            println!("{}", format.serialize(&stats)?);
+            Ok(())
+        },
+        | args::Command::Multiplex {
+            commands,
+            workflow,
+            nodes,
+            args,
+            secret_args,
+            secrets,
+            workers,
+            history,
+            restart,
+            exit_on_first_failure,
+        } => {
+            let mut exec_engine = ExecutionEngine::default().with_workers(workers).with_secrets(secrets);
+            if let Some(history) = history {
+                exec_engine = exec_engine.with_history(History::new(history));
             }
-
-            let (report_tx, report_rx) = flume::unbounded::<Option<(String, String)>>();
-            let report_fut = tokio::spawn(async move {
-                for update in report_rx.iter() {
-                    yield_now().await; // make sure it's abortable
-                    if let Some((cmd, state)) = update {
-                        command_states.insert(cmd, state);
-                    }
-
-                    let mut writer = BufWriter::new(stdout());
-                    crossterm::queue!(writer, Clear(ClearType::All)).unwrap();
-                    crossterm::queue!(writer, MoveTo(0, 0)).unwrap();
-
-                    writeln!(writer, "Executing commands:").unwrap();
-                    for item in command_states.iter() {
-                        writeln!(writer, "⇒ {}", item.0).unwrap();
-                        writeln!(writer, " ↳ Status: {}", item.1).unwrap();
-                    }
-                    writer.flush().unwrap();
-                    sleep(Duration::from_secs(1));
-                }
-            });
-            report_tx.send(None).unwrap(); // first draw
-
-            let mut joins = JoinSet::new();
+            let mut multiplex = Multiplex::new()
+                .with_engine(exec_engine.clone())
+                .with_restart(restart)
+                .with_exit_on_first_failure(exit_on_first_failure);
             for command in commands {
-                let report_channel = report_tx.clone();
-                joins.spawn(async move {
-                    let mut cmd_proc = Command::new("sh");
-                    cmd_proc.args(&["-c", &command]);
-                    cmd_proc.stdin(std::process::Stdio::null());
-                    cmd_proc.stdout(std::process::Stdio::null());
-                    cmd_proc.stderr(std::process::Stdio::null());
-                    let mut child_proc = cmd_proc.spawn().unwrap();
-                    let exit_code = child_proc.wait().await.unwrap();
-                    let status = if exit_code.success() {
-                        "SUCCESS".to_owned()
-                    } else {
-                        format!("FAILED ({})", exit_code.code().unwrap())
-                    };
-                    report_channel.send(Some((command.clone(), status))).unwrap();
-                });
+                multiplex = multiplex.command(command);
             }
-            drop(report_tx);
-
-            let mut signals = Signals::new([SIGINT, SIGTERM]).unwrap();
-            let signals_handle = signals.handle();
-            let abort_fut = tokio::spawn(async move { signals.wait() });
-            let command_fut = tokio::spawn(async move { while let Some(_) = joins.join_next().await {} });
-            tokio::select! {
-                _ = abort_fut => {
-                    println!("signal received... aborting...");
-                },
-                _ = command_fut => {
-                    println!("completed all tasks... shutting down...")
-                },
-                _ = report_fut => {
-                },
+            if let Some(workflow) = workflow {
+                let w = Workflow::load(&workflow)?;
+                let c = Compiler::new(w).with_secret_args(secret_args);
+                let (pre, invocations) = multiplex::split(&c.plan(&nodes, &args)?)?;
+                // prerequisites run to completion before the UI starts
+                if !pre.stages.is_empty() {
+                    execute(&exec_engine, &pre)?;
+                }
+                for (title, plan) in invocations {
+                    multiplex = multiplex.plan(title, plan);
+                }
             }
-            signals_handle.close();
-
+            multiplex.run()?;
             Ok(())
         },
-        | crate::args::Command::Watch {
+        | args::Command::Watch {
             workflow,
-            watch,
+            watches,
             args,
+            secret_args,
+            secrets,
+            history,
             workers,
             root,
+            lock_dir,
+            poll,
         } => {
             let w = Workflow::load(&workflow)?;
-            let watch = match &w.watch {
-                | Some(v) => {
-                    if let Some(v) = v.get(&watch) {
-                        v
-                    } else {
-                        Err(crate::error::Error::NotFound(format!(
-                            "no watch node named {} in config",
-                            watch
-                        )))?
-                    }
-                },
-                | None => Err(crate::error::Error::NotFound("no watch node in config".to_owned()))?,
+            let defs = match &w.watch {
+                | Some(v) => v.clone(),
+                | None => Err(Error::NotFound("no watch node in config".to_owned()))?,
             };
-            let nodes = match &watch.exec {
-                | WatchExecStep::Node { ref_ } => Nodes::Arr(HashSet::<String>::from_iter([ref_.clone()])),
+            let watches = match watches {
+                | Some(v) => v,
+                | None => defs.keys().cloned().sorted().collect_vec(),
             };
-            let nodes = nodes.select(&w)?;
-            let regex = fancy_regex::Regex::new(&watch.filter)?;
-            let exec_state = Arc::new(if watch.queue {
-                None
-            } else {
-                Some(Mutex::new(Cell::new(false)))
-            });
-            let c = Compiler::new(w);
-            let exec_engine = Arc::new(ExecutionEngine::new(OutputMode {
-                stdout: true,
-                stderr: true,
-            }));
-            let trim_path =
-                std::fs::canonicalize(&root).unwrap().to_str().unwrap().to_owned() + std::path::MAIN_SEPARATOR_STR;
-            let exec_state_callback = exec_state.clone();
-
-            let mut watcher = RecommendedWatcher::new(
-                move |result: Result<notify::Event, notify::Error>| {
-                    match result {
-                        | Ok(e) => {
-                            let event_kind = match &e.kind {
-                                | notify::EventKind::Create(v) => {
-                                    match &v {
-                                        | notify::event::CreateKind::Any => "created/any",
-                                        | notify::event::CreateKind::File => "created/file",
-                                        | notify::event::CreateKind::Folder => "created/folder",
-                                        | notify::event::CreateKind::Other => "created/other",
-                                    }
-                                },
-                                | notify::EventKind::Modify(v) => {
-                                    match &v {
-                                        | notify::event::ModifyKind::Any => "modified/any",
-                                        | notify::event::ModifyKind::Data(v) => {
-                                            match &v {
-                                                | notify::event::DataChange::Any => "modified/data/any",
-                                                | notify::event::DataChange::Size => "modified/data/size",
-                                                | notify::event::DataChange::Content => "modified/data/content",
-                                                | notify::event::DataChange::Other => "modified/data/other",
-                                            }
-                                        },
-                                        | notify::event::ModifyKind::Metadata(v) => {
-                                            match &v {
-                                                | notify::event::MetadataKind::Any => "modified/metadata/any",
-                                                | notify::event::MetadataKind::AccessTime => {
-                                                    "modified/metadata/accesstime"
-                                                },
-                                                | notify::event::MetadataKind::WriteTime => {
-                                                    "modified/metadata/writetime"
-                                                },
-                                                | notify::event::MetadataKind::Permissions => {
-                                                    "modified/metadata/permissions"
-                                                },
-                                                | notify::event::MetadataKind::Ownership => {
-                                                    "modified/metadata/ownership"
-                                                },
-                                                | notify::event::MetadataKind::Extended => "modified/metadata/extended",
-                                                | notify::event::MetadataKind::Other => "modified/metadata/other",
-                                            }
-                                        },
-                                        | notify::event::ModifyKind::Name(v) => {
-                                            match &v {
-                                                | notify::event::RenameMode::Any => "modified/name/any",
-                                                | notify::event::RenameMode::To => "modified/name/to",
-                                                | notify::event::RenameMode::From => "modified/name/from",
-                                                | notify::event::RenameMode::Both => "modified/name/both",
-                                                | notify::event::RenameMode::Other => "modified/name/other",
-                                            }
-                                        },
-                                        | notify::event::ModifyKind::Other => "modified/other",
-                                    }
-                                },
-                                | notify::EventKind::Remove(v) => {
-                                    match &v {
-                                        | notify::event::RemoveKind::Any => "removed/any",
-                                        | notify::event::RemoveKind::File => "removed/file",
-                                        | notify::event::RemoveKind::Folder => "removed/folder",
-                                        | notify::event::RemoveKind::Other => "removed/other",
-                                    }
-                                },
-                                | notify::EventKind::Other => "other",
-                                | notify::EventKind::Any => "any",
-                                | notify::EventKind::Access(k) => {
-                                    match &k {
-                                        | notify::event::AccessKind::Any => "access/any",
-                                        | notify::event::AccessKind::Read => "access/read",
-                                        | notify::event::AccessKind::Open(v) => {
-                                            match &v {
-                                                | notify::event::AccessMode::Any => "access/open/any",
-                                                | notify::event::AccessMode::Execute => "access/open/execute",
-                                                | notify::event::AccessMode::Read => "access/open/read",
-                                                | notify::event::AccessMode::Write => "access/open/write",
-                                                | notify::event::AccessMode::Other => "access/open/other",
-                                            }
-                                        },
-                                        | notify::event::AccessKind::Close(v) => {
-                                            match &v {
-                                                | notify::event::AccessMode::Any => "access/close/any",
-                                                | notify::event::AccessMode::Execute => "access/close/execute",
-                                                | notify::event::AccessMode::Read => "access/close/read",
-                                                | notify::event::AccessMode::Write => "access/close/write",
-                                                | notify::event::AccessMode::Other => "access/close/other",
-                                            }
-                                        },
-                                        | notify::event::AccessKind::Other => "other",
-                                    }
-                                },
-                            };
 
-                            let event_path = e.paths[0].to_str().unwrap().trim_start_matches(&trim_path);
-                            let filter = format!("{}|{}", &event_kind, &event_path);
-                            if regex.is_match(&filter).unwrap() {
-                                match exec_state_callback.deref() {
-                                    | Some(v) => {
-                                        let state_lock = v.lock().unwrap();
-                                        if state_lock.get() {
-                                            return;
-                                        }
-                                        state_lock.set(true);
-                                    },
-                                    | None => {},
-                                }
-
-                                let mut args_new = args.clone();
-                                args_new.insert("EVENT".to_owned(), filter);
-                                args_new.insert("EVENT_KIND".to_owned(), event_kind.to_owned());
-                                args_new.insert("EVENT_PATH".to_owned(), event_path.to_owned());
-                                let plan = c.plan(&nodes, &args_new).unwrap();
-                                let exec_engine_thread = exec_engine.clone();
-                                let state_thread = exec_state_callback.clone();
-                                std::thread::spawn(move || {
-                                    exec_engine_thread.execute(&plan, workers).unwrap();
-                                    match state_thread.deref() {
-                                        | Some(v) => {
-                                            v.lock().unwrap().set(false);
-                                        },
-                                        | None => {},
-                                    }
-                                });
+            let mut selected = Vec::<(String, WatchExec, Vec<NodeRef>)>::new();
+            for name in watches {
+                let mut def = match defs.get(&name) {
+                    | Some(v) => v.clone(),
+                    | None => Err(Error::NotFound(format!("no watch node named {} in config", name)))?,
+                };
+                if let Some(interval) = poll {
+                    let compare_contents = match def.backend {
+                        | Some(WatchBackend::Poll { compare_contents, .. }) => compare_contents,
+                        | _ => None,
+                    };
+                    def.backend = Some(WatchBackend::Poll {
+                        interval,
+                        compare_contents,
+                    });
+                }
+                let mut nodes = Vec::<NodeRef>::new();
+                for step in def.exec.steps() {
+                    match step {
+                        | WatchExecStep::Node { ref_, args } => {
+                            if !w.nodes.contains_key(ref_) {
+                                Err(Error::NotFound(ref_.to_owned()))?
                             }
-                        },
-                        | Err(e) => {
-                            println!("{:?}", e);
+                            let mut node = NodeRef::from(ref_.as_str());
+                            node.args = args.clone().unwrap_or_default();
+                            nodes.push(node);
                         },
                     }
-                },
-                notify::Config::default(),
-            )?;
-            watcher.watch(Path::new(&root), notify::RecursiveMode::Recursive)?;
-            loop {}
+                }
+                selected.push((name, def, nodes));
+            }
+
+            let c = Arc::new(Compiler::new(w).with_secret_args(secret_args));
+            let mut exec_engine = ExecutionEngine::default().with_workers(workers).with_secrets(secrets);
+            if let Some(history) = history {
+                exec_engine = exec_engine.with_history(History::new(history));
+            }
+            if let Some(lock_dir) = lock_dir {
+                exec_engine = exec_engine.with_lock_dir(PathBuf::from(lock_dir));
+            }
+            let exec_engine = Arc::new(exec_engine);
+
+            let mut signals = Signals::new([SIGINT, SIGTERM])?;
+            let mut handles = Vec::new();
+            for (name, def, nodes) in selected {
+                handles.push(
+                    Watch {
+                        name,
+                        def,
+                        compiler: c.clone(),
+                        engine: exec_engine.clone(),
+                        nodes,
+                        args: args.clone(),
+                        root: root.clone(),
+                    }
+                    .start()?,
+                );
+            }
+
+            // block until a signal arrives, the watches run in their own threads
+            signals.forever().next();
+            println!("signal received... shutting down...");
+            for handle in handles {
+                handle.stop();
+            }
+            Ok(())
         },
     }
 }
+
+/// Executes the plan and reports a failure to record it in the history.
+fn execute(engine: &ExecutionEngine, plan: &neomake::plan::ExecutionPlan) -> Result<()> {
+    let recorded = engine.execute_recorded(plan, None);
+    if let Some(e) = recorded.history {
+        eprintln!("failed to record the run in the history: {}", e);
+    }
+    recorded.result
+}
+
+fn report_skipped(skipped: &[Skipped]) {
+    for v in skipped {
+        eprintln!("skipping history file {}: {}", v.path.to_string_lossy(), v.error);
+    }
+}
@@ -0,0 +1,709 @@
+use {
+    crate::args::RestartPolicy,
+    anyhow::Result,
+    crossterm::{
+        cursor::{
+            Hide,
+            MoveTo,
+            Show,
+        },
+        event::{
+            Event as InputEvent,
+            KeyCode,
+            KeyEvent,
+            KeyEventKind,
+            KeyModifiers,
+        },
+        style::{
+            Print,
+            PrintStyledContent,
+            Stylize,
+        },
+        terminal::{
+            Clear,
+            ClearType,
+            EnterAlternateScreen,
+            LeaveAlternateScreen,
+        },
+    },
+    neomake::{
+        error::Error,
+        exec::{
+            Cancellation,
+            ExecutionEngine,
+        },
+        plan::{
+            self,
+            ExecutionPlan,
+        },
+    },
+    signal_hook::{
+        consts::SIGTERM,
+        iterator::Signals,
+    },
+    std::{
+        collections::{
+            HashMap,
+            VecDeque,
+        },
+        io::{
+            stdout,
+            BufWriter,
+            Write,
+        },
+        thread::JoinHandle,
+        time::{
+            Duration,
+            Instant,
+        },
+    },
+};
+
+/// The number of output lines that are kept per command.
+const SCROLLBACK: usize = 10000;
+/// The delay before the first automatic restart, doubled per attempt.
+const BACKOFF_BASE: Duration = Duration::from_millis(500);
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+/// Runs that lasted at least this long reset the backoff.
+const BACKOFF_RESET: Duration = Duration::from_secs(10);
+
+/// Events that are processed by the UI loop.
+enum Event {
+    Output {
+        idx: usize,
+        run: usize,
+        line: String,
+    },
+    Exited {
+        idx: usize,
+        run: usize,
+        status: Status,
+    },
+    /// A scheduled automatic restart.
+    Restart {
+        idx: usize,
+        run: usize,
+    },
+    /// The previous run of a restarted command exited, the next one can start.
+    Start {
+        idx: usize,
+        run: usize,
+    },
+    Input(InputEvent),
+    Quit,
+}
+
+#[derive(Debug, Clone)]
+enum Status {
+    Running,
+    Success,
+    Failed(i32),
+    Terminated,
+    Error(String),
+}
+
+impl Status {
+    fn is_failure(&self) -> bool {
+        matches!(self, Self::Failed(_) | Self::Error(_))
+    }
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            | Self::Running => write!(f, "RUNNING"),
+            | Self::Success => write!(f, "SUCCESS"),
+            | Self::Failed(code) => write!(f, "FAILED ({})", code),
+            | Self::Terminated => write!(f, "TERMINATED"),
+            | Self::Error(e) => write!(f, "ERROR ({})", e),
+        }
+    }
+}
+
+/// A multiplexed command or node invocation and its captured output.
+struct Pane {
+    title: String,
+    /// The plan of a single invocation.
+    plan: ExecutionPlan,
+    lines: VecDeque<String>,
+    status: Status,
+    /// Counts the runs so that events of a previous (restarted) run can be
+    /// told apart.
+    run: usize,
+    cancellation: Cancellation,
+    /// The thread running the current run, or terminating the previous one
+    /// while a restart is pending.
+    handle: Option<JoinHandle<()>>,
+    /// Number of lines scrolled up from the bottom. Zero follows the output.
+    scroll: usize,
+    started: Instant,
+    /// Number of automatic restarts in a row after short runs, which
+    /// determines the backoff.
+    attempts: u32,
+}
+
+impl Pane {
+    fn push(&mut self, line: String) {
+        if self.lines.len() == SCROLLBACK {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+        if self.scroll > 0 {
+            // keep the viewport in place while scrolled up
+            self.scroll = (self.scroll + 1).min(self.lines.len());
+        }
+    }
+}
+
+/// Restores the terminal when the UI is left, including on panics.
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> Result<Self> {
+        crossterm::terminal::enable_raw_mode()?;
+        crossterm::execute!(stdout(), EnterAlternateScreen, Hide)?;
+        Ok(Self)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        crossterm::execute!(stdout(), Show, LeaveAlternateScreen).ok();
+        crossterm::terminal::disable_raw_mode().ok();
+    }
+}
+
+/// Runs commands in parallel and shows their output in an interactive
+/// terminal UI with one pane per command.
+pub(crate) struct Multiplex {
+    panes: Vec<Pane>,
+    /// Executes the plans of the panes, each with its own output sink.
+    engine: ExecutionEngine,
+    restart: RestartPolicy,
+    exit_on_first_failure: bool,
+    focus: usize,
+    zoom: bool,
+    tx: flume::Sender<Event>,
+    rx: flume::Receiver<Event>,
+}
+
+impl Multiplex {
+    pub fn new() -> Self {
+        let (tx, rx) = flume::unbounded::<Event>();
+        Self {
+            panes: Vec::new(),
+            engine: ExecutionEngine::default(),
+            restart: RestartPolicy::Never,
+            exit_on_first_failure: false,
+            focus: 0,
+            zoom: false,
+            tx,
+            rx,
+        }
+    }
+
+    pub fn with_engine(mut self, engine: ExecutionEngine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    pub fn with_restart(mut self, restart: RestartPolicy) -> Self {
+        self.restart = restart;
+        self
+    }
+
+    pub fn with_exit_on_first_failure(mut self, exit_on_first_failure: bool) -> Self {
+        self.exit_on_first_failure = exit_on_first_failure;
+        self
+    }
+
+    /// Adds a raw command that is executed via `sh -c`.
+    pub fn command(self, command: String) -> Self {
+        let node = plan::Node {
+            invocations: vec![plan::Invocation::default()],
+            tasks: vec![plan::Task {
+                action: plan::Action::Script(command.clone()),
+                env: HashMap::new(),
+                shell: None,
+                workdir: None,
+                interactive: false,
+                stdin: None,
+            }],
+            env: HashMap::new(),
+            shell: None,
+            workdir: None,
+            executor: None,
+            sandbox: None,
+            resources: None,
+            lock: None,
+            parallelism: None,
+            pre: vec![],
+        };
+        self.plan(command.clone(), ExecutionPlan {
+            nodes: HashMap::from([(command.clone(), node)]),
+            stages: vec![plan::Stage { nodes: vec![command] }],
+            selection: vec![],
+            args: HashMap::new(),
+            env: HashMap::new(),
+            secrets: vec![],
+            resources: HashMap::new(),
+        })
+    }
+
+    /// Adds the plan of a single invocation, see [`split`].
+    pub fn plan(mut self, title: String, plan: ExecutionPlan) -> Self {
+        self.panes.push(Pane {
+            title,
+            plan,
+            lines: VecDeque::new(),
+            status: Status::Running,
+            run: 0,
+            cancellation: Cancellation::new(),
+            handle: None,
+            scroll: 0,
+            started: Instant::now(),
+            attempts: 0,
+        });
+        self
+    }
+
+    /// Starts all commands and runs the UI until it is quit. All commands that
+    /// are still running are terminated then. Fails if any command failed in
+    /// its last run.
+    pub fn run(mut self) -> Result<()> {
+        if self.panes.is_empty() {
+            Err(Error::Argument("no commands given".to_owned()))?
+        }
+        let guard = TerminalGuard::enter()?;
+
+        let tx = self.tx.clone();
+        std::thread::spawn(move || {
+            while let Ok(e) = crossterm::event::read() {
+                if tx.send(Event::Input(e)).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut signals = Signals::new([SIGTERM])?;
+        let tx = self.tx.clone();
+        std::thread::spawn(move || {
+            if signals.forever().next().is_some() {
+                tx.send(Event::Quit).ok();
+            }
+        });
+
+        for idx in 0..self.panes.len() {
+            self.spawn(idx);
+        }
+        self.draw()?;
+
+        while let Ok(first) = self.rx.recv() {
+            let mut quit = false;
+            for event in std::iter::once(first).chain(self.rx.try_iter().collect::<Vec<_>>()) {
+                quit |= self.handle(event);
+            }
+            if quit {
+                break;
+            }
+            self.draw()?;
+        }
+
+        // commands that ignore SIGTERM are killed after a grace period, all of them at
+        // the same time
+        let stopping = self
+            .panes
+            .iter_mut()
+            .filter_map(|pane| {
+                let cancellation = pane.cancellation.clone();
+                pane.handle
+                    .take()
+                    .map(|handle| std::thread::spawn(move || cancellation.terminate(handle).ok()))
+            })
+            .collect::<Vec<_>>();
+        for stop in stopping {
+            stop.join().ok();
+        }
+        for pane in self.panes.iter_mut() {
+            if let Status::Running = pane.status {
+                pane.status = Status::Terminated;
+            }
+        }
+        drop(guard);
+
+        for (idx, pane) in self.panes.iter().enumerate() {
+            println!("⇒ [{}] {} | {}", idx + 1, pane.title, pane.status);
+        }
+        let failed = self.panes.iter().filter(|p| p.status.is_failure()).count();
+        if failed > 0 {
+            Err(Error::ChildProcess(format!(
+                "{} of {} commands failed",
+                failed,
+                self.panes.len()
+            )))?
+        }
+        Ok(())
+    }
+
+    /// Applies the event to the state. Returns whether to quit.
+    fn handle(&mut self, event: Event) -> bool {
+        match event {
+            | Event::Output { idx, run, line } => {
+                if self.panes[idx].run == run {
+                    self.panes[idx].push(line);
+                }
+            },
+            | Event::Exited { idx, run, status } => {
+                if self.panes[idx].run != run {
+                    return false;
+                }
+                let failure = status.is_failure();
+                self.panes[idx].status = status;
+                if failure && self.exit_on_first_failure {
+                    return true;
+                }
+                if self.restart == RestartPolicy::Always || (self.restart == RestartPolicy::OnFailure && failure) {
+                    self.schedule_restart(idx);
+                }
+            },
+            | Event::Restart { idx, run } => {
+                if self.panes[idx].run == run {
+                    self.restart(idx);
+                }
+            },
+            | Event::Start { idx, run } => {
+                if self.panes[idx].run == run {
+                    self.spawn(idx);
+                }
+            },
+            | Event::Input(InputEvent::Key(key)) => return self.key(key),
+            | Event::Input(_) => {},
+            | Event::Quit => return true,
+        }
+        false
+    }
+
+    fn key(&mut self, key: KeyEvent) -> bool {
+        if key.kind == KeyEventKind::Release {
+            return false;
+        }
+        let page = self.pane_height().saturating_sub(1).max(1);
+        let pane = &mut self.panes[self.focus];
+        match key.code {
+            | KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return true,
+            | KeyCode::Char('q') => return true,
+            | KeyCode::Tab | KeyCode::Right | KeyCode::Char('l') => {
+                self.focus = (self.focus + 1) % self.panes.len();
+            },
+            | KeyCode::BackTab | KeyCode::Left | KeyCode::Char('h') => {
+                self.focus = (self.focus + self.panes.len() - 1) % self.panes.len();
+            },
+            | KeyCode::Char('z') | KeyCode::Enter => self.zoom = !self.zoom,
+            | KeyCode::Up | KeyCode::Char('k') => pane.scroll = (pane.scroll + 1).min(pane.lines.len()),
+            | KeyCode::Down | KeyCode::Char('j') => pane.scroll = pane.scroll.saturating_sub(1),
+            | KeyCode::PageUp => pane.scroll = (pane.scroll + page).min(pane.lines.len()),
+            | KeyCode::PageDown => pane.scroll = pane.scroll.saturating_sub(page),
+            | KeyCode::Home | KeyCode::Char('g') => pane.scroll = pane.lines.len(),
+            | KeyCode::End | KeyCode::Char('G') => pane.scroll = 0,
+            | KeyCode::Char('r') => {
+                pane.attempts = 0;
+                self.restart(self.focus);
+            },
+            | _ => {},
+        }
+        false
+    }
+
+    /// Restarts the command after a delay that grows with every restart in a
+    /// row after a short run.
+    fn schedule_restart(&mut self, idx: usize) {
+        let pane = &mut self.panes[idx];
+        if pane.started.elapsed() >= BACKOFF_RESET {
+            pane.attempts = 0;
+        }
+        let delay = BACKOFF_BASE
+            .saturating_mul(2u32.saturating_pow(pane.attempts))
+            .min(BACKOFF_MAX);
+        pane.attempts += 1;
+        pane.push(format!("--- restarting in {:.1}s ---", delay.as_secs_f64()));
+
+        let (run, tx) = (pane.run, self.tx.clone());
+        std::thread::spawn(move || {
+            std::thread::sleep(delay);
+            tx.send(Event::Restart { idx, run }).ok();
+        });
+    }
+
+    /// Terminates the command if it is still running and starts it again once
+    /// it exited.
+    fn restart(&mut self, idx: usize) {
+        let pane = &mut self.panes[idx];
+        let cancellation = std::mem::replace(&mut pane.cancellation, Cancellation::new());
+        pane.run += 1;
+        pane.push(format!("--- restarted (run {}) ---", pane.run + 1));
+        let (run, tx) = (pane.run, self.tx.clone());
+        match pane.handle.take() {
+            | Some(handle) => {
+                // waiting for the previous run (up to the grace period) must not block the UI
+                pane.handle = Some(std::thread::spawn(move || {
+                    cancellation.terminate(handle).ok();
+                    tx.send(Event::Start { idx, run }).ok();
+                }));
+            },
+            | None => self.spawn(idx),
+        }
+    }
+
+    /// Executes the plan of the pane. Its output and exit status are sent to
+    /// the UI loop.
+    fn spawn(&mut self, idx: usize) {
+        let pane = &mut self.panes[idx];
+        pane.status = Status::Running;
+        pane.scroll = 0;
+        pane.started = Instant::now();
+        let run = pane.run;
+
+        let (sink_tx, sink_rx) = flume::unbounded::<String>();
+        let tx = self.tx.clone();
+        let forward = std::thread::spawn(move || {
+            for line in sink_rx.iter() {
+                let line = sanitize(line.trim_end_matches(['\n', '\r']));
+                if tx.send(Event::Output { idx, run, line }).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let engine = self.engine.clone().with_sink(sink_tx);
+        let plan = pane.plan.clone();
+        let cancellation = pane.cancellation.clone();
+        let tx = self.tx.clone();
+        pane.handle = Some(std::thread::spawn(move || {
+            // tasks of the invocation run one after another
+            let recorded = engine.execute_recorded(&plan, Some(cancellation.clone()));
+            drop(engine);
+            forward.join().ok();
+            if let Some(e) = recorded.history {
+                let line = sanitize(&format!("failed to record the run in the history: {}", e));
+                tx.send(Event::Output { idx, run, line }).ok();
+            }
+            let status = match recorded.result {
+                | Ok(_) => Status::Success,
+                | Err(_) if cancellation.is_cancelled() => Status::Terminated,
+                | Err(e) => {
+                    match recorded.tasks.iter().find_map(|r| r.exit_code.filter(|c| *c != 0)) {
+                        | Some(code) => Status::Failed(code),
+                        | None => Status::Error(e.to_string()),
+                    }
+                },
+            };
+            tx.send(Event::Exited { idx, run, status }).ok();
+        }));
+    }
+
+    /// The height of a single pane (incl. its header) in the split view.
+    fn pane_height(&self) -> usize {
+        let (_, rows) = crossterm::terminal::size().unwrap_or((80, 24));
+        let rows = (rows as usize).saturating_sub(1);
+        if self.zoom {
+            rows
+        } else {
+            rows / self.panes.len()
+        }
+    }
+
+    fn draw(&self) -> Result<()> {
+        let (cols, rows) = crossterm::terminal::size()?;
+        let (cols, rows) = (cols as usize, rows as usize);
+        let mut writer = BufWriter::new(stdout());
+        crossterm::queue!(writer, Clear(ClearType::All))?;
+
+        let visible = if self.zoom {
+            vec![self.focus]
+        } else {
+            (0..self.panes.len()).collect()
+        };
+        let height = self.pane_height();
+        for (slot, idx) in visible.into_iter().enumerate() {
+            let pane = &self.panes[idx];
+            let top = slot * height;
+            let header = truncate(&format!("⇒ [{}] {} | {}", idx + 1, pane.title, pane.status), cols);
+            crossterm::queue!(writer, MoveTo(0, top as u16))?;
+            if idx == self.focus {
+                crossterm::queue!(writer, PrintStyledContent(header.reverse()))?;
+            } else {
+                crossterm::queue!(writer, PrintStyledContent(header.bold()))?;
+            }
+
+            let lines = height.saturating_sub(1);
+            let end = pane.lines.len() - pane.scroll;
+            let start = end.saturating_sub(lines);
+            for (row, line) in pane.lines.range(start..end).enumerate() {
+                crossterm::queue!(writer, MoveTo(0, (top + 1 + row) as u16), Print(truncate(line, cols)))?;
+            }
+        }
+
+        let help = "tab/←/→ focus | z zoom | ↑/↓/pgup/pgdn/g/G scroll | r restart | q quit";
+        crossterm::queue!(
+            writer,
+            MoveTo(0, rows.saturating_sub(1) as u16),
+            PrintStyledContent(truncate(help, cols).dim())
+        )?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Removes escape sequences and control characters that would break the
+/// layout.
+fn sanitize(line: &str) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            | '\x1b' => {
+                // skip CSI sequences like colors entirely
+                if chars.peek() == Some(&'[') {
+                    chars.next();
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+            },
+            | '\t' => result.push_str("    "),
+            | c if c.is_control() => {},
+            | c => result.push(c),
+        }
+    }
+    result
+}
+
+fn truncate(line: &str, width: usize) -> String {
+    line.chars().take(width).collect()
+}
+
+/// Splits the plan into the plans of the invocations of the selected nodes
+/// (with their titles) and a plan for all other nodes, which are the
+/// prerequisites of the selected ones. Fails if a prerequisite depends on a
+/// selected node, since it would have to run while the panes are shown.
+pub(crate) fn split(plan: &ExecutionPlan) -> Result<(ExecutionPlan, Vec<(String, ExecutionPlan)>)> {
+    let mut pre = plan.clone();
+    for stage in pre.stages.iter_mut() {
+        stage.nodes.retain(|v| !plan.selection.contains(v));
+    }
+    pre.stages.retain(|v| !v.nodes.is_empty());
+    pre.nodes.retain(|k, _| !plan.selection.contains(k));
+    for id in pre.stages.iter().flat_map(|v| &v.nodes) {
+        if let Some(selected) = pre.nodes[id].pre.iter().find(|v| plan.selection.contains(v)) {
+            Err(Error::Argument(format!(
+                "node {} depends on the selected node {} and can not run before the multiplexer starts",
+                id, selected
+            )))?
+        }
+    }
+
+    let mut invocations = Vec::<(String, ExecutionPlan)>::new();
+    for id in &plan.selection {
+        let node = &plan.nodes[id];
+        for invocation in &node.invocations {
+            let title = match invocation.coords.as_str() {
+                | "" => id.clone(),
+                | coords => format!("{} [{}]", id, coords),
+            };
+            let mut single = node.clone();
+            single.invocations = vec![invocation.clone()];
+            single.pre = vec![];
+            invocations.push((title, ExecutionPlan {
+                nodes: HashMap::from([(id.clone(), single)]),
+                stages: vec![plan::Stage {
+                    nodes: vec![id.clone()],
+                }],
+                selection: vec![id.clone()],
+                args: plan.args.clone(),
+                env: plan.env.clone(),
+                secrets: plan.secrets.clone(),
+                resources: plan.resources.clone(),
+            }));
+        }
+    }
+    Ok((pre, invocations))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        neomake::{
+            Compiler,
+            Workflow,
+        },
+        std::collections::HashSet,
+    };
+
+    fn plan(workflow: &str, nodes: &[&str]) -> ExecutionPlan {
+        Compiler::new(Workflow::load(workflow).unwrap())
+            .plan(
+                &nodes.iter().map(|v| v.to_string()).collect::<HashSet<_>>(),
+                &HashMap::new(),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn split_runs_prerequisites_first() {
+        let (pre, invocations) = split(&plan(
+            r#"
+version: "0.0"
+nodes:
+  build:
+    tasks: []
+  api:
+    pre: [build]
+    tasks: []
+  worker:
+    matrix:
+      dense:
+        dimensions:
+          - - env: { N: "0" }
+            - env: { N: "1" }
+    tasks: []
+"#,
+            &["api", "worker"],
+        ))
+        .unwrap();
+        assert_eq!(pre.nodes.keys().collect::<Vec<_>>(), vec!["build"]);
+        assert_eq!(pre.stages.len(), 1);
+        assert_eq!(invocations.iter().map(|v| v.0.as_str()).collect::<Vec<_>>(), vec![
+            "api",
+            "worker [0]",
+            "worker [1]"
+        ]);
+        assert!(invocations
+            .iter()
+            .all(|v| v.1.stages.len() == 1 && v.1.nodes.len() == 1));
+    }
+
+    #[test]
+    fn split_rejects_prerequisites_of_selected_nodes() {
+        let res = split(&plan(
+            r#"
+version: "0.0"
+nodes:
+  db:
+    tasks: []
+  migrate:
+    pre: [db]
+    tasks: []
+  api:
+    pre: [migrate]
+    tasks: []
+"#,
+            &["db", "api"],
+        ));
+        match res.err().and_then(|e| e.downcast::<Error>().ok()) {
+            | Some(Error::Argument(v)) => assert!(v.starts_with("node migrate depends on the selected node db")),
+            | v => panic!("unexpected result {:?}", v),
+        }
+    }
+}
@@ -1,23 +1,43 @@
 use std::collections::HashMap;
 
+/// The value secret args are rendered as. Replaced with the value of the secret
+/// right before a task runs.
+pub fn secret_placeholder(name: &str) -> String {
+    format!("{{{{secret:{}}}}}", name)
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "snake_case", deny_unknown_fields)]
-pub(crate) struct ExecutionPlan {
+pub struct ExecutionPlan {
     pub nodes: HashMap<String, Node>,
     pub stages: Vec<Stage>,
+    /// The nodes that were selected for planning.
+    #[serde(default)]
+    pub selection: Vec<String>,
+    /// The args the plan was rendered with.
+    #[serde(default)]
+    pub args: HashMap<String, String>,
 
     pub env: HashMap<String, String>,
+    /// Names of secret env vars and args. Their values are not part of the
+    /// plan and are resolved from the secrets given to the engine or the
+    /// environment of the executing process.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub secrets: Vec<String>,
+    /// Capacities of the named resources.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub resources: HashMap<String, u64>,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "snake_case", deny_unknown_fields)]
-pub(crate) struct Stage {
+pub struct Stage {
     pub nodes: Vec<String>,
 }
 
 #[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(rename_all = "snake_case", deny_unknown_fields)]
-pub(crate) struct Shell {
+pub struct Shell {
     pub program: String,
     pub args: Vec<String>,
 }
@@ -33,28 +53,133 @@ impl From<crate::workflow::Shell> for Shell {
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "snake_case", deny_unknown_fields)]
-pub(crate) struct Node {
+pub struct Node {
     pub invocations: Vec<Invocation>,
     pub tasks: Vec<Task>,
 
     pub env: HashMap<String, String>,
     pub shell: Option<Shell>,
     pub workdir: Option<String>,
+    /// The name of the executor running the script tasks, the local one if
+    /// unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub executor: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<Sandbox>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources: Option<Resources>,
+    /// The name of the lock that the invocations of this node hold while they
+    /// run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock: Option<String>,
+    /// The maximum number of invocations that run at the same time. They are
+    /// started in order.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parallelism: Option<usize>,
+    /// The ids of the nodes that have to be executed before this node.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre: Vec<String>,
+}
+
+/// The resources every task of a node occupies while it runs.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub struct Resources {
+    pub cpu: u64,
+    /// In bytes.
+    pub memory: Option<u64>,
+    /// Whether the address space of the scripts is limited to `memory`.
+    pub limit_memory: bool,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub named: HashMap<String, u64>,
+}
+
+/// Isolation of the script tasks of a node (Linux only).
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub struct Sandbox {
+    /// Paths that stay writable, relative to the workdir of the task.
+    pub outputs: Vec<String>,
+    pub network: bool,
 }
 
 #[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(rename_all = "snake_case", deny_unknown_fields)]
-pub(crate) struct Invocation {
+pub struct Invocation {
     pub coords: String,
     pub env: HashMap<String, String>,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "snake_case", deny_unknown_fields)]
-pub(crate) struct Task {
-    pub cmd: String,
+pub struct Task {
+    #[serde(with = "serde_yaml::with::singleton_map_recursive")]
+    pub action: Action,
 
     pub env: HashMap<String, String>,
     pub shell: Option<Shell>,
     pub workdir: Option<String>,
+    /// Whether the script gets the terminal and runs exclusively.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub interactive: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "serde_yaml::with::singleton_map_recursive")]
+    pub stdin: Option<Stdin>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Stdin {
+    Content(String),
+    /// A path relative to the workdir, read when the script starts.
+    File(String),
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Action {
+    Script(String),
+    Copy {
+        from: String,
+        to: String,
+    },
+    Mkdir(String),
+    Remove(String),
+    Write {
+        path: String,
+        content: String,
+    },
+    WaitFor {
+        target: WaitTarget,
+        timeout: u64,
+        interval: u64,
+    },
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum WaitTarget {
+    Tcp(String),
+    File(String),
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            | Self::Script(v) => write!(f, "{}", v),
+            | Self::Copy { from, to } => write!(f, "copy {} to {}", from, to),
+            | Self::Mkdir(v) => write!(f, "mkdir {}", v),
+            | Self::Remove(v) => write!(f, "remove {}", v),
+            | Self::Write { path, .. } => write!(f, "write {}", path),
+            | Self::WaitFor { target, .. } => write!(f, "wait for {}", target),
+        }
+    }
+}
+
+impl std::fmt::Display for WaitTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            | Self::Tcp(v) => write!(f, "tcp {}", v),
+            | Self::File(v) => write!(f, "file {}", v),
+        }
+    }
 }
@@ -0,0 +1,281 @@
+use {
+    crate::{
+        error::Error,
+        plan,
+    },
+    anyhow::Result,
+    std::{
+        ffi::CString,
+        path::{
+            Path,
+            PathBuf,
+        },
+    },
+};
+
+/// Mounts below these paths keep their flags, everything else becomes
+/// read-only.
+const KEEP: [&str; 3] = ["/dev", "/proc", "/sys"];
+/// Replaced by an empty tmpfs.
+const TMP: &str = "/tmp";
+
+/// A mount that is remounted read-only along with the flags that have to be
+/// preserved.
+struct Remount {
+    target: CString,
+    flags: libc::c_ulong,
+}
+
+/// Everything needed to enter the sandbox in the child process, prepared in
+/// the parent as allocating after the fork is not safe.
+pub(crate) struct Prepared {
+    /// The uid and gid maps, set when a user namespace is required.
+    maps: Option<(CString, CString)>,
+    network: bool,
+    outputs: Vec<CString>,
+    remounts: Vec<Remount>,
+    workdir: CString,
+}
+
+/// Decodes the octal escapes (like `\040` for spaces) in mountinfo paths.
+fn unescape(v: &str) -> String {
+    let mut out = Vec::<u8>::new();
+    let bytes = v.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 4 <= bytes.len() && bytes[i + 1..i + 4].iter().all(|v| (b'0'..=b'7').contains(v)) {
+            out.push(u8::from_str_radix(&v[i + 1..i + 4], 8).unwrap_or(b'?'));
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn under(path: &Path, base: &str) -> bool {
+    path.starts_with(base)
+}
+
+impl Prepared {
+    /// Resolves the outputs relative to the workdir (creating missing ones as
+    /// directories) and collects the mounts to remount read-only.
+    pub(crate) fn new(sandbox: &plan::Sandbox, workdir: Option<&str>) -> Result<Self> {
+        let workdir = match workdir {
+            | Some(v) => std::env::current_dir()?.join(v),
+            | None => std::env::current_dir()?,
+        };
+
+        let mut outputs = Vec::<PathBuf>::new();
+        for output in &sandbox.outputs {
+            let path = workdir.join(output);
+            if !path.exists() {
+                std::fs::create_dir_all(&path)?;
+            }
+            let path = path.canonicalize()?;
+            if under(&path, TMP) {
+                Err(Error::Argument(format!(
+                    "sandbox output {} is inside of {}",
+                    path.to_string_lossy(),
+                    TMP
+                )))?
+            }
+            outputs.push(path);
+        }
+
+        let mut remounts = Vec::<Remount>::new();
+        for line in std::fs::read_to_string("/proc/self/mountinfo")?.lines() {
+            // id parent major:minor root mount-point options ...
+            let fields = line.split(' ').collect::<Vec<_>>();
+            if fields.len() < 6 {
+                continue;
+            }
+            let target = PathBuf::from(unescape(fields[4]));
+            if KEEP.iter().any(|v| under(&target, v)) || under(&target, TMP) || outputs.contains(&target) {
+                continue;
+            }
+            let mut flags = libc::MS_REMOUNT | libc::MS_BIND | libc::MS_RDONLY;
+            for option in fields[5].split(',') {
+                flags |= match option {
+                    | "nosuid" => libc::MS_NOSUID,
+                    | "nodev" => libc::MS_NODEV,
+                    | "noexec" => libc::MS_NOEXEC,
+                    | "noatime" => libc::MS_NOATIME,
+                    | "nodiratime" => libc::MS_NODIRATIME,
+                    | "relatime" => libc::MS_RELATIME,
+                    | _ => 0,
+                };
+            }
+            remounts.push(Remount {
+                target: CString::new(target.as_os_str().as_encoded_bytes())?,
+                flags,
+            });
+        }
+
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        Ok(Self {
+            // root can create the namespaces without a user namespace
+            maps: match uid {
+                | 0 => None,
+                | _ => {
+                    Some((
+                        CString::new(format!("{} {} 1", uid, uid))?,
+                        CString::new(format!("{} {} 1", gid, gid))?,
+                    ))
+                },
+            },
+            network: sandbox.network,
+            outputs: outputs
+                .iter()
+                .map(|v| CString::new(v.as_os_str().as_encoded_bytes()))
+                .collect::<std::result::Result<_, _>>()?,
+            remounts,
+            workdir: CString::new(workdir.as_os_str().as_encoded_bytes())?,
+        })
+    }
+
+    /// Enters the sandbox. Runs in the forked child right before the exec and
+    /// must therefore only use async-signal-safe functions.
+    ///
+    /// The child forks once more so that the task is the first process of the
+    /// new PID namespace, the intermediate process waits for it and exits with
+    /// its status.
+    pub(crate) fn enter(&self) -> std::io::Result<()> {
+        fn check(res: libc::c_int) -> std::io::Result<()> {
+            match res {
+                | -1 => Err(std::io::Error::last_os_error()),
+                | _ => Ok(()),
+            }
+        }
+
+        unsafe {
+            let mut flags = libc::CLONE_NEWNS | libc::CLONE_NEWPID;
+            if self.maps.is_some() {
+                flags |= libc::CLONE_NEWUSER;
+            }
+            if !self.network {
+                flags |= libc::CLONE_NEWNET;
+            }
+            check(libc::unshare(flags))?;
+
+            if let Some((uid_map, gid_map)) = &self.maps {
+                write(c"/proc/self/setgroups", c"deny")?;
+                write(c"/proc/self/uid_map", uid_map)?;
+                write(c"/proc/self/gid_map", gid_map)?;
+            }
+
+            // keep the mounts from propagating to the host
+            check(libc::mount(
+                std::ptr::null(),
+                c"/".as_ptr(),
+                std::ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                std::ptr::null(),
+            ))?;
+            // bind the outputs onto themselves so that they are separate mounts that
+            // stay writable
+            for output in &self.outputs {
+                check(libc::mount(
+                    output.as_ptr(),
+                    output.as_ptr(),
+                    std::ptr::null(),
+                    libc::MS_BIND | libc::MS_REC,
+                    std::ptr::null(),
+                ))?;
+            }
+            for remount in &self.remounts {
+                check(libc::mount(
+                    std::ptr::null(),
+                    remount.target.as_ptr(),
+                    std::ptr::null(),
+                    remount.flags,
+                    std::ptr::null(),
+                ))?;
+            }
+            check(libc::mount(
+                c"tmpfs".as_ptr(),
+                c"/tmp".as_ptr(),
+                c"tmpfs".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV,
+                std::ptr::null(),
+            ))?;
+            // the workdir might be an output, which is now a different mount
+            libc::chdir(self.workdir.as_ptr());
+
+            match libc::fork() {
+                | -1 => return Err(std::io::Error::last_os_error()),
+                | 0 => {},
+                | pid => wait_and_exit(pid),
+            }
+
+            // the task must not outlive the intermediate process, which receives the
+            // signals of the process group
+            check(libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL))?;
+            // the processes of the namespace only, fails if /proc is not mountable
+            libc::mount(
+                c"proc".as_ptr(),
+                c"/proc".as_ptr(),
+                c"proc".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                std::ptr::null(),
+            );
+            if !self.network {
+                loopback_up();
+            }
+        }
+        Ok(())
+    }
+}
+
+unsafe fn write(path: &std::ffi::CStr, content: &std::ffi::CStr) -> std::io::Result<()> {
+    let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+    if fd == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let bytes = content.to_bytes();
+    let res = libc::write(fd, bytes.as_ptr() as *const libc::c_void, bytes.len());
+    libc::close(fd);
+    match res {
+        | -1 => Err(std::io::Error::last_os_error()),
+        | _ => Ok(()),
+    }
+}
+
+/// Waits for the task and exits with its status. Closes all inherited file
+/// descriptors first, so that the exec status pipe of the parent is only held
+/// by the task.
+unsafe fn wait_and_exit(pid: libc::pid_t) -> ! {
+    if libc::syscall(libc::SYS_close_range, 3, libc::c_uint::MAX, 0) == -1 {
+        for fd in 3..1024 {
+            libc::close(fd);
+        }
+    }
+    let mut status = 0;
+    while libc::waitpid(pid, &mut status, 0) == -1 {
+        if *libc::__errno_location() != libc::EINTR {
+            libc::_exit(1);
+        }
+    }
+    if libc::WIFSIGNALED(status) {
+        libc::_exit(128 + libc::WTERMSIG(status));
+    }
+    libc::_exit(libc::WEXITSTATUS(status))
+}
+
+/// Brings up the loopback interface of the new network namespace.
+unsafe fn loopback_up() {
+    let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+    if fd == -1 {
+        return;
+    }
+    let mut req: libc::ifreq = std::mem::zeroed();
+    for (i, c) in b"lo".iter().enumerate() {
+        req.ifr_name[i] = *c as libc::c_char;
+    }
+    if libc::ioctl(fd, libc::SIOCGIFFLAGS, &mut req) != -1 {
+        req.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+        libc::ioctl(fd, libc::SIOCSIFFLAGS, &mut req);
+    }
+    libc::close(fd);
+}
@@ -0,0 +1,529 @@
+use {
+    anyhow::Result,
+    globset::{
+        GlobBuilder,
+        GlobSet,
+        GlobSetBuilder,
+    },
+    ignore::gitignore::{
+        Gitignore,
+        GitignoreBuilder,
+    },
+    itertools::Itertools,
+    neomake::{
+        compiler::{
+            Compiler,
+            NodeRef,
+        },
+        error::Error,
+        exec::{
+            Cancellation,
+            ExecutionEngine,
+        },
+        workflow::{
+            WatchBackend,
+            WatchExec,
+        },
+    },
+    notify::{
+        PollWatcher,
+        RecommendedWatcher,
+        Watcher,
+    },
+    std::{
+        collections::HashMap,
+        path::{
+            Path,
+            PathBuf,
+        },
+        sync::{
+            Arc,
+            Mutex,
+        },
+        thread::JoinHandle,
+        time::Duration,
+    },
+};
+
+/// Maps the notify event kind to the identifier used in watch filters.
+pub(crate) fn event_kind(kind: &notify::EventKind) -> &'static str {
+    match kind {
+        | notify::EventKind::Create(v) => {
+            match &v {
+                | notify::event::CreateKind::Any => "created/any",
+                | notify::event::CreateKind::File => "created/file",
+                | notify::event::CreateKind::Folder => "created/folder",
+                | notify::event::CreateKind::Other => "created/other",
+            }
+        },
+        | notify::EventKind::Modify(v) => {
+            match &v {
+                | notify::event::ModifyKind::Any => "modified/any",
+                | notify::event::ModifyKind::Data(v) => {
+                    match &v {
+                        | notify::event::DataChange::Any => "modified/data/any",
+                        | notify::event::DataChange::Size => "modified/data/size",
+                        | notify::event::DataChange::Content => "modified/data/content",
+                        | notify::event::DataChange::Other => "modified/data/other",
+                    }
+                },
+                | notify::event::ModifyKind::Metadata(v) => {
+                    match &v {
+                        | notify::event::MetadataKind::Any => "modified/metadata/any",
+                        | notify::event::MetadataKind::AccessTime => "modified/metadata/accesstime",
+                        | notify::event::MetadataKind::WriteTime => "modified/metadata/writetime",
+                        | notify::event::MetadataKind::Permissions => "modified/metadata/permissions",
+                        | notify::event::MetadataKind::Ownership => "modified/metadata/ownership",
+                        | notify::event::MetadataKind::Extended => "modified/metadata/extended",
+                        | notify::event::MetadataKind::Other => "modified/metadata/other",
+                    }
+                },
+                | notify::event::ModifyKind::Name(v) => {
+                    match &v {
+                        | notify::event::RenameMode::Any => "modified/name/any",
+                        | notify::event::RenameMode::To => "modified/name/to",
+                        | notify::event::RenameMode::From => "modified/name/from",
+                        | notify::event::RenameMode::Both => "modified/name/both",
+                        | notify::event::RenameMode::Other => "modified/name/other",
+                    }
+                },
+                | notify::event::ModifyKind::Other => "modified/other",
+            }
+        },
+        | notify::EventKind::Remove(v) => {
+            match &v {
+                | notify::event::RemoveKind::Any => "removed/any",
+                | notify::event::RemoveKind::File => "removed/file",
+                | notify::event::RemoveKind::Folder => "removed/folder",
+                | notify::event::RemoveKind::Other => "removed/other",
+            }
+        },
+        | notify::EventKind::Other => "other",
+        | notify::EventKind::Any => "any",
+        | notify::EventKind::Access(k) => {
+            match &k {
+                | notify::event::AccessKind::Any => "access/any",
+                | notify::event::AccessKind::Read => "access/read",
+                | notify::event::AccessKind::Open(v) => {
+                    match &v {
+                        | notify::event::AccessMode::Any => "access/open/any",
+                        | notify::event::AccessMode::Execute => "access/open/execute",
+                        | notify::event::AccessMode::Read => "access/open/read",
+                        | notify::event::AccessMode::Write => "access/open/write",
+                        | notify::event::AccessMode::Other => "access/open/other",
+                    }
+                },
+                | notify::event::AccessKind::Close(v) => {
+                    match &v {
+                        | notify::event::AccessMode::Any => "access/close/any",
+                        | notify::event::AccessMode::Execute => "access/close/execute",
+                        | notify::event::AccessMode::Read => "access/close/read",
+                        | notify::event::AccessMode::Write => "access/close/write",
+                        | notify::event::AccessMode::Other => "access/close/other",
+                    }
+                },
+                | notify::event::AccessKind::Other => "other",
+            }
+        },
+    }
+}
+
+/// The filter for watches that do not define one.
+const DEFAULT_FILTER: &str = "^(created|modified|removed)/";
+
+/// Returns the directory part of the glob that does not contain any pattern.
+fn glob_base(glob: &str) -> String {
+    let parts = glob.split('/').collect_vec();
+    parts[..parts.len() - 1]
+        .iter()
+        .take_while(|p| !p.contains(['*', '?', '[', '{']))
+        .join("/")
+}
+
+/// Watches the given directory (or file). Without ignore matcher, the directory
+/// is watched recursively. Otherwise all of its directories that are not
+/// ignored are watched individually so that ignored ones do not cause any
+/// events.
+fn watch_tree(watcher: &mut dyn Watcher, dir: &Path, ignore: &Option<Gitignore>) -> Result<()> {
+    let ignore = match ignore {
+        | Some(v) => v,
+        | None => return Ok(watcher.watch(dir, notify::RecursiveMode::Recursive)?),
+    };
+    if !dir.is_dir() {
+        return Ok(watcher.watch(dir, notify::RecursiveMode::NonRecursive)?);
+    }
+    let walker = walkdir::WalkDir::new(dir).into_iter().filter_entry(|e| {
+        e.depth() == 0 || !(e.file_type().is_dir() && ignore.matched_path_or_any_parents(e.path(), true).is_ignore())
+    });
+    for entry in walker {
+        let entry = entry?;
+        if entry.file_type().is_dir() {
+            watcher.watch(entry.path(), notify::RecursiveMode::NonRecursive)?;
+        }
+    }
+    Ok(())
+}
+
+/// Resolves `.` and `..` components without accessing the filesystem.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            | std::path::Component::CurDir => {},
+            | std::path::Component::ParentDir => {
+                normalized.pop();
+            },
+            | v => normalized.push(v),
+        }
+    }
+    normalized
+}
+
+/// A filesystem event that passed the watch filter.
+#[derive(Debug, Clone)]
+struct Change {
+    kind: &'static str,
+    path: String,
+}
+
+impl Change {
+    fn filter(&self) -> String {
+        format!("{}|{}", self.kind, self.path)
+    }
+}
+
+/// Handle of a started watch.
+pub(crate) struct WatchHandle {
+    watcher: Arc<Mutex<Box<dyn Watcher+Send>>>,
+    stop_tx: flume::Sender<()>,
+    dispatcher: JoinHandle<()>,
+}
+
+impl WatchHandle {
+    /// Stops watching, terminates the running executions and waits for them
+    /// to finish.
+    pub fn stop(self) {
+        drop(self.watcher);
+        self.stop_tx.send(()).ok();
+        self.dispatcher.join().ok();
+    }
+}
+
+/// A single watch session that executes the referenced nodes on changes.
+pub(crate) struct Watch {
+    pub name: String,
+    pub def: WatchExec,
+    pub compiler: Arc<Compiler>,
+    pub engine: Arc<ExecutionEngine>,
+    pub nodes: Vec<NodeRef>,
+    pub args: HashMap<String, String>,
+    /// The root that the watched paths and the event paths are relative to.
+    pub root: String,
+}
+
+impl Watch {
+    /// Starts watching the paths. The watch continues until the returned
+    /// handle is stopped.
+    pub fn start(self) -> Result<WatchHandle> {
+        let regex = fancy_regex::Regex::new(self.def.filter.as_deref().unwrap_or(DEFAULT_FILTER))?;
+        let root = std::fs::canonicalize(&self.root)?;
+        let inputs = match self.def.from_inputs {
+            | Some(true) => Some(self.compiler.inputs(&self.nodes)?),
+            | _ => None,
+        };
+        let input_matcher = match &inputs {
+            | Some(v) => Some(self.input_matcher(v)?),
+            | None => None,
+        };
+        let trim_path = root.to_string_lossy().into_owned() + std::path::MAIN_SEPARATOR_STR;
+        let ignore = Arc::new(self.ignore_matcher(&root)?);
+        let (change_tx, change_rx) = flume::unbounded::<Change>();
+        let (dir_tx, dir_rx) = flume::unbounded::<PathBuf>();
+
+        let ignore_callback = ignore.clone();
+        let name = self.name.clone();
+        // runs on the watcher thread, so failures are reported instead of panicking
+        let handler = move |result: Result<notify::Event, notify::Error>| {
+            match result {
+                | Ok(e) => {
+                    let path = match e.paths.first() {
+                        | Some(v) => v,
+                        | None => return,
+                    };
+                    if let Some(ignore) = ignore_callback.as_ref() {
+                        if ignore.matched_path_or_any_parents(path, path.is_dir()).is_ignore() {
+                            return;
+                        }
+                        // directories are watched individually, new ones need to be added
+                        if matches!(e.kind, notify::EventKind::Create(_)) && path.is_dir() {
+                            dir_tx.send(path.clone()).ok();
+                        }
+                    }
+                    let change = Change {
+                        kind: event_kind(&e.kind),
+                        path: path.to_string_lossy().trim_start_matches(&trim_path).to_owned(),
+                    };
+                    if let Some(inputs) = &input_matcher {
+                        if !inputs.is_match(&change.path) {
+                            return;
+                        }
+                    }
+                    match regex.is_match(&change.filter()) {
+                        | Ok(true) => {
+                            // the dispatcher is gone once the watch is stopped
+                            change_tx.send(change).ok();
+                        },
+                        | Ok(false) => {},
+                        | Err(e) => eprintln!("watch {}: filter failed: {}", name, e),
+                    }
+                },
+                | Err(e) => {
+                    eprintln!("watch {}: {}", name, e);
+                },
+            }
+        };
+        let watcher: Box<dyn Watcher+Send> = match &self.def.backend {
+            | None | Some(WatchBackend::Native) => {
+                Box::new(RecommendedWatcher::new(handler, notify::Config::default())?)
+            },
+            | Some(WatchBackend::Poll {
+                interval,
+                compare_contents,
+            }) => {
+                Box::new(PollWatcher::new(
+                    handler,
+                    notify::Config::default()
+                        .with_poll_interval(Duration::from_millis(*interval))
+                        .with_compare_contents(compare_contents.unwrap_or(false)),
+                )?)
+            },
+        };
+        let watcher = Arc::new(Mutex::new(watcher));
+        let mut paths = self.def.paths.clone().unwrap_or_default();
+        if let Some(inputs) = &inputs {
+            paths.extend(inputs.iter().map(|v| glob_base(v)));
+        }
+        if paths.is_empty() || paths.iter().any(|p| p.is_empty()) {
+            paths = vec![String::new()];
+        }
+        let paths = paths
+            .iter()
+            .map(|p| {
+                // the ignore matcher and the event paths require paths below the root
+                match normalize(&root.join(p)) {
+                    | v if v.starts_with(&root) => Ok(v),
+                    | _ => {
+                        Err(Error::Argument(format!(
+                            "path {} of watch {} is outside of the root {}",
+                            p,
+                            self.name,
+                            root.display()
+                        )))
+                    },
+                }
+            })
+            .collect::<std::result::Result<Vec<_>, _>>()?
+            .into_iter()
+            .unique()
+            .collect_vec();
+        // nested paths are already covered by their parents
+        let paths = paths
+            .iter()
+            .filter(|p| !paths.iter().any(|o| o != *p && p.starts_with(o)))
+            .collect_vec();
+        for path in paths {
+            if !path.exists() {
+                Err(Error::NotFound(format!(
+                    "path {} of watch {}",
+                    path.display(),
+                    self.name
+                )))?
+            }
+            watch_tree(&mut **watcher.lock().unwrap(), path, &ignore)?;
+        }
+
+        let watcher_dirs = watcher.clone();
+        let name = self.name.clone();
+        std::thread::spawn(move || {
+            for dir in dir_rx.iter() {
+                if let Err(e) = watch_tree(&mut **watcher_dirs.lock().unwrap(), &dir, &ignore) {
+                    eprintln!("watch {}: {}", name, e);
+                }
+            }
+        });
+
+        let (stop_tx, stop_rx) = flume::bounded::<()>(1);
+        let watch = Arc::new(self);
+        let dispatcher = std::thread::spawn(move || watch.dispatch(change_rx, stop_rx));
+        Ok(WatchHandle {
+            watcher,
+            stop_tx,
+            dispatcher,
+        })
+    }
+
+    /// Builds the matcher for the input globs of the nodes.
+    fn input_matcher(&self, inputs: &[String]) -> Result<GlobSet> {
+        if inputs.is_empty() {
+            Err(Error::NotFound(format!("inputs of the nodes of watch {}", self.name)))?
+        }
+        let mut builder = GlobSetBuilder::new();
+        for input in inputs {
+            builder.add(GlobBuilder::new(input).literal_separator(true).build()?);
+        }
+        Ok(builder.build()?)
+    }
+
+    /// Builds the matcher for ignored paths from the `ignore` globs and
+    /// optionally the `.gitignore` file in the root.
+    fn ignore_matcher(&self, root: &Path) -> Result<Option<Gitignore>> {
+        let globs = self.def.ignore.clone().unwrap_or_default();
+        let gitignore = self.def.gitignore.unwrap_or(false);
+        if globs.is_empty() && !gitignore {
+            return Ok(None);
+        }
+
+        let mut builder = GitignoreBuilder::new(root);
+        if gitignore {
+            builder.add_line(None, ".git/")?;
+            let file = root.join(".gitignore");
+            if file.exists() {
+                if let Some(e) = builder.add(file) {
+                    Err(e)?
+                }
+            }
+        }
+        for glob in globs {
+            builder.add_line(None, &glob)?;
+        }
+        Ok(Some(builder.build()?))
+    }
+
+    /// Receives the changes, merges them according to the debounce and batch
+    /// settings and triggers the executions until a stop is requested. Running
+    /// executions are terminated on stop.
+    fn dispatch(self: Arc<Self>, change_rx: flume::Receiver<Change>, stop_rx: flume::Receiver<()>) {
+        let debounce = self.def.debounce.map(|v| {
+            let max = self.def.debounce_max.unwrap_or(v.saturating_mul(10));
+            (Duration::from_millis(v), Duration::from_millis(max))
+        });
+        let batch = self.def.batch.unwrap_or(false);
+        let restart = self.def.restart.unwrap_or(false);
+        let mut runs = Vec::<(Cancellation, JoinHandle<()>)>::new();
+        if self.def.initial_run.unwrap_or(false) {
+            runs.extend(self.trigger("", "", "", Vec::new()));
+        }
+
+        loop {
+            let first = flume::Selector::new()
+                .recv(&change_rx, |v| v.ok())
+                .recv(&stop_rx, |_| None)
+                .wait();
+            let mut changes = match first {
+                | Some(v) => vec![v],
+                | None => break,
+            };
+            if let Some((debounce, max)) = debounce {
+                // wait until no further change arrives within the window, but no longer than
+                // the maximum so that a steady stream of changes does not starve the execution
+                let deadline = std::time::Instant::now() + max;
+                loop {
+                    let timeout = debounce.min(deadline.saturating_duration_since(std::time::Instant::now()));
+                    if timeout.is_zero() {
+                        break;
+                    }
+                    match change_rx.recv_timeout(timeout) {
+                        | Ok(next) => changes.push(next),
+                        | Err(_) => break,
+                    }
+                }
+            }
+            if batch {
+                changes.extend(change_rx.try_iter());
+            } else {
+                changes = changes.split_off(changes.len() - 1);
+            }
+
+            runs.retain(|(_, handle)| !handle.is_finished());
+            if restart {
+                for (cancellation, handle) in runs.drain(..) {
+                    cancellation.terminate(handle).ok();
+                }
+            } else if !self.def.queue && !runs.is_empty() {
+                continue;
+            }
+
+            let last = changes.last().unwrap();
+            let paths = changes
+                .iter()
+                .map(|c| c.path.clone())
+                .collect::<std::collections::BTreeSet<_>>()
+                .into_iter()
+                .collect_vec();
+            runs.extend(self.trigger(&last.filter(), last.kind, &last.path, paths));
+        }
+
+        for (cancellation, handle) in runs {
+            cancellation.terminate(handle).ok();
+        }
+    }
+
+    /// Plans and starts an execution for the given change. Prints a status line
+    /// with the trigger, the duration and the result once it finished.
+    fn trigger(
+        self: &Arc<Self>,
+        event: &str,
+        kind: &str,
+        path: &str,
+        paths: Vec<String>,
+    ) -> Option<(Cancellation, JoinHandle<()>)> {
+        let mut args = self.args.clone();
+        args.insert("EVENT".to_owned(), event.to_owned());
+        args.insert("EVENT_KIND".to_owned(), kind.to_owned());
+        args.insert("EVENT_PATH".to_owned(), path.to_owned());
+        let mut values = serde_json::Map::new();
+        values.insert(
+            "EVENT_PATHS".to_owned(),
+            serde_json::Value::Array(paths.into_iter().map(serde_json::Value::String).collect()),
+        );
+        let trigger = match path {
+            | "" => "<initial>".to_owned(),
+            | v => v.to_owned(),
+        };
+
+        let plan = match self.compiler.plan_with_values(&self.nodes, &args, &values) {
+            | Ok(v) => v,
+            | Err(e) => {
+                eprintln!("watch {}: planning failed: {}", self.name, e);
+                println!("⇒ {} | {} | - | FAILED", self.name, trigger);
+                return None;
+            },
+        };
+        let cancellation = Cancellation::new();
+        let c = cancellation.clone();
+        let watch = self.clone();
+        let handle = std::thread::spawn(move || {
+            let start = std::time::Instant::now();
+            let recorded = watch.engine.execute_recorded(&plan, Some(c.clone()));
+            if let Some(e) = recorded.history {
+                eprintln!("watch {}: failed to record the run in the history: {}", watch.name, e);
+            }
+            let status = if c.is_cancelled() {
+                "CANCELLED"
+            } else if let Err(e) = recorded.result {
+                eprintln!("watch {}: execution failed: {}", watch.name, e);
+                "FAILED"
+            } else {
+                "SUCCESS"
+            };
+            println!(
+                "⇒ {} | {} | {:.2}s | {}",
+                watch.name,
+                trigger,
+                start.elapsed().as_secs_f64(),
+                status
+            );
+        });
+        Some((cancellation, handle))
+    }
+}
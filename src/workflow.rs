@@ -2,13 +2,16 @@ use {
     crate::error::Error,
     anyhow::Result,
     itertools::Itertools,
-    std::collections::HashMap,
+    std::collections::{
+        BTreeMap,
+        HashMap,
+    },
 };
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(rename_all = "snake_case")] // can not deny unknown fields to support YAML anchors
 /// The entire workflow definition.
-pub(crate) struct Workflow {
+pub struct Workflow {
     /// The version of this workflow file (major.minor).
     pub version: String,
     /// Env vars.
@@ -19,9 +22,17 @@ pub(crate) struct Workflow {
     #[schemars(with = "HashMap<String, Node>")]
     /// All nodes.
     pub nodes: HashMap<String, Node>,
+    #[serde(default, with = "serde_yaml::with::singleton_map_recursive")]
+    #[schemars(with = "Option<HashMap<String, Node>>")]
+    /// Abstract node templates that nodes can inherit from via `extends`.
+    /// Templates are not runnable by themselves.
+    pub templates: Option<HashMap<String, Node>>,
 
     /// All watch nodes.
     pub watch: Option<HashMap<String, WatchExec>>,
+    /// Capacities of named resources (like `db: 1`) that nodes can occupy via
+    /// their `resources`.
+    pub resources: Option<HashMap<String, u64>>,
 }
 
 impl Workflow {
@@ -42,15 +53,56 @@ impl Workflow {
             )))?
         }
 
-        let wf: crate::workflow::Workflow = serde_yaml::from_str(&data)?;
+        let mut wf: crate::workflow::Workflow = serde_yaml::from_str(&data)?;
         let nodes_allow_regex = fancy_regex::Regex::new(r"^[a-zA-Z0-9_-]+$")?;
-        for node in wf.nodes.keys() {
+        for node in wf.nodes.keys().chain(wf.templates.iter().flat_map(|t| t.keys())) {
             if !nodes_allow_regex.is_match(node)? {
                 Err(Error::InvalidNodeName(node.clone()))?
             }
         }
+        // lock names are used as file names
+        for lock in wf
+            .nodes
+            .values()
+            .chain(wf.templates.iter().flat_map(|t| t.values()))
+            .flat_map(|v| &v.lock)
+        {
+            if !nodes_allow_regex.is_match(lock)? {
+                Err(Error::Argument(format!("invalid lock name {}", lock)))?
+            }
+        }
+        wf.resolve_templates()?;
         Ok(wf)
     }
+
+    /// Replaces every node that extends a template with the merged result.
+    fn resolve_templates(&mut self) -> Result<()> {
+        fn resolve(templates: &HashMap<String, Node>, node: &Node, chain: &mut Vec<String>) -> Result<Node> {
+            let base = match &node.extends {
+                | Some(v) => v,
+                | None => return Ok(node.clone()),
+            };
+            if chain.contains(base) {
+                chain.push(base.clone());
+                Err(Error::TemplateRecursion(chain.join(" -> ")))?
+            }
+            let template = match templates.get(base) {
+                | Some(v) => v,
+                | None => Err(Error::NotFound(format!("template {}", base)))?,
+            };
+            chain.push(base.clone());
+            let base = resolve(templates, template, chain)?;
+            Ok(node.clone().extend(base))
+        }
+
+        // nodes and templates are separate namespaces, the chain only contains
+        // templates
+        let templates = self.templates.clone().unwrap_or_default();
+        for node in self.nodes.values_mut() {
+            *node = resolve(&templates, node, &mut vec![])?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
@@ -59,19 +111,25 @@ impl Workflow {
 pub struct Env {
     /// Regex for capturing and storing env vars during compile time.
     pub capture: Option<String>,
+    /// Regex for marking captured or explicitly set env vars as secret. Their
+    /// values are never written into the plan but resolved from the secrets or
+    /// the environment at execution time and masked in any forwarded output.
+    pub secret: Option<String>,
     /// Explicitly set env vars.
     pub vars: Option<HashMap<String, String>>,
 }
 
 impl Env {
     pub(crate) fn compile(&self) -> Result<HashMap<String, String>> {
+        let secrets = self.compile_secrets()?;
         let mut map = self.vars.clone().or(Some(HashMap::<_, _>::new())).unwrap();
+        map.retain(|k, _| !secrets.contains(k));
         match &self.capture {
             | Some(v) => {
                 let regex = fancy_regex::Regex::new(v)?;
                 let envs = std::env::vars().collect_vec();
                 for e in envs {
-                    if regex.is_match(&e.0)? {
+                    if regex.is_match(&e.0)? && !secrets.contains(&e.0) {
                         map.insert(e.0, e.1);
                     }
                 }
@@ -80,12 +138,36 @@ impl Env {
         }
         Ok(map)
     }
+
+    pub(crate) fn compile_secrets(&self) -> Result<Vec<String>> {
+        let secret = match &self.secret {
+            | Some(v) => fancy_regex::Regex::new(v)?,
+            | None => return Ok(vec![]),
+        };
+        let mut secrets = Vec::<String>::new();
+        for k in self.vars.iter().flat_map(|v| v.keys()) {
+            if secret.is_match(k)? {
+                secrets.push(k.clone());
+            }
+        }
+        if let Some(capture) = &self.capture {
+            let capture = fancy_regex::Regex::new(capture)?;
+            for (k, _) in std::env::vars() {
+                if capture.is_match(&k)? && secret.is_match(&k)? {
+                    secrets.push(k);
+                }
+            }
+        }
+        secrets.sort();
+        secrets.dedup();
+        Ok(secrets)
+    }
 }
 
 #[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(rename_all = "snake_case", deny_unknown_fields)]
 /// A task execution environment.
-pub(crate) struct Shell {
+pub struct Shell {
     /// The program (like "/bin/bash").
     pub program: String,
     /// Custom args (like \["-c"\]).
@@ -95,16 +177,21 @@ pub(crate) struct Shell {
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(rename_all = "snake_case", deny_unknown_fields)]
 /// An individual node for executing a task batch.
-pub(crate) struct Node {
+pub struct Node {
     /// A description of this node.
     pub description: Option<String>,
+    /// The template this node inherits from. Unset fields are taken from the
+    /// template, `env` is merged (node wins) and `pre`, `inputs` and `tasks`
+    /// of the template come before the ones of the node.
+    pub extends: Option<String>,
     /// Reference nodes that need to be executed prior to this one.
-    pub pre: Option<Vec<String>>,
+    pub pre: Option<Vec<Pre>>,
 
     /// An n-dimensional matrix that is executed for every item in its cartesian
     /// product.
     pub matrix: Option<Matrix>,
     /// The tasks to be executed.
+    #[serde(default)]
     pub tasks: Vec<Task>,
 
     /// Env vars.
@@ -113,12 +200,147 @@ pub(crate) struct Node {
     pub shell: Option<Shell>,
     /// Custom workdir.
     pub workdir: Option<String>,
+    /// Globs of the files this node reads, relative to the watch root. Used by
+    /// watches with `from_inputs`.
+    pub inputs: Option<Vec<String>>,
+    /// The executor that runs the scripts of this node (like "local", which is
+    /// the default). Executors other than the local one are provided by the
+    /// tool embedding neomake.
+    pub executor: Option<String>,
+    /// Runs the scripts isolated from the host (Linux only).
+    pub sandbox: Option<Sandbox>,
+    /// The share of the execution capacities every task of this node occupies
+    /// while it runs.
+    pub resources: Option<Resources>,
+    /// The name of a lock. Invocations of nodes with the same lock never run at
+    /// the same time.
+    pub lock: Option<String>,
+    /// The maximum number of invocations (matrix entries) of this node that run
+    /// at the same time.
+    pub parallelism: Option<usize>,
+    /// Runs the invocations one after another in the order of the matrix.
+    /// Shortcut for `parallelism: 1`.
+    pub sequential: Option<bool>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")] // can not deny unknown fields due to the named resources
+/// Resources that a task occupies while it runs. Tasks only start once all of
+/// their resources are available.
+pub struct Resources {
+    /// Units of the worker capacity (`--workers`). Defaults to 1.
+    pub cpu: Option<u64>,
+    /// Memory (like "512M" or "8G"), counted against the memory capacity of
+    /// the execution (`--memory`). Ignored if no capacity is set.
+    pub memory: Option<String>,
+    /// Limits the address space of the scripts to `memory` (via rlimit).
+    pub limit_memory: Option<bool>,
+    /// Units of named resources declared in the workflow `resources`.
+    #[serde(flatten)]
+    pub named: BTreeMap<String, u64>,
+}
+
+impl Resources {
+    /// Parses a memory size in bytes (like "1024", "512K", "16M" or "8G").
+    pub fn parse_memory(v: &str) -> Result<u64> {
+        let v = v.trim();
+        let (number, factor) = match v.char_indices().last() {
+            | Some((i, 'K' | 'k')) => (&v[..i], 1 << 10),
+            | Some((i, 'M' | 'm')) => (&v[..i], 1 << 20),
+            | Some((i, 'G' | 'g')) => (&v[..i], 1 << 30),
+            | Some((i, 'T' | 't')) => (&v[..i], 1 << 40),
+            | _ => (v, 1),
+        };
+        match number.trim().parse::<u64>().ok().map(|n| n.checked_mul(factor)) {
+            | Some(Some(v)) => Ok(v),
+            | Some(None) => Err(Error::Argument(format!("memory size {} is too large", v)))?,
+            | None => Err(Error::Argument(format!("invalid memory size {}", v)))?,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+/// Runs scripts in new mount, PID and network namespaces. The filesystem is
+/// read-only except for the outputs, `/dev`, `/proc`, `/sys` and an empty
+/// `/tmp`.
+pub struct Sandbox {
+    /// Paths that stay writable, relative to the workdir. Missing paths are
+    /// created as directories.
+    pub outputs: Option<Vec<String>>,
+    /// Allows network access. Defaults to false.
+    pub network: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(untagged)]
+/// A reference to a node that needs to be executed prior to another one.
+pub enum Pre {
+    /// The name of the node.
+    Name(String),
+    /// The node with parameters for its invocation.
+    Parameterized(PreNode),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+/// A parameterized node reference. Nodes are deduplicated on the node name in
+/// combination with all of its parameters.
+pub struct PreNode {
+    /// The name of the node.
+    pub node: String,
+    /// Values for handlebars placeholders, replacing the ones given via the
+    /// command line.
+    pub args: Option<BTreeMap<String, String>>,
+    /// Env vars, replacing the ones defined in the node.
+    pub env: Option<BTreeMap<String, String>>,
+    /// Regex for the matrix coords of the invocations to keep.
+    pub matrix: Option<String>,
+}
+
+impl Node {
+    /// Merges this node on top of the given (already resolved) base node.
+    fn extend(self, base: Node) -> Node {
+        let env = match (base.env, self.env) {
+            | (Some(mut b), Some(n)) => {
+                b.extend(n);
+                Some(b)
+            },
+            | (b, n) => n.or(b),
+        };
+        let pre = match (base.pre, self.pre) {
+            | (Some(b), Some(n)) => Some(b.into_iter().chain(n).unique().collect_vec()),
+            | (b, n) => n.or(b),
+        };
+        let inputs = match (base.inputs, self.inputs) {
+            | (Some(b), Some(n)) => Some(b.into_iter().chain(n).unique().collect_vec()),
+            | (b, n) => n.or(b),
+        };
+
+        Node {
+            description: self.description.or(base.description),
+            extends: None,
+            pre,
+            matrix: self.matrix.or(base.matrix),
+            tasks: base.tasks.into_iter().chain(self.tasks).collect_vec(),
+            env,
+            shell: self.shell.or(base.shell),
+            workdir: self.workdir.or(base.workdir),
+            inputs,
+            executor: self.executor.or(base.executor),
+            sandbox: self.sandbox.or(base.sandbox),
+            resources: self.resources.or(base.resources),
+            lock: self.lock.or(base.lock),
+            parallelism: self.parallelism.or(base.parallelism),
+            sequential: self.sequential.or(base.sequential),
+        }
+    }
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(rename_all = "snake_case", deny_unknown_fields)]
 /// An entry in the n-dimensional matrix for the node execution.
-pub(crate) enum Matrix {
+pub enum Matrix {
     Dense {
         drop: Option<String>,
         dimensions: Vec<Vec<MatrixCell>>,
@@ -198,17 +420,32 @@ impl Matrix {
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(rename_all = "snake_case", deny_unknown_fields)]
 /// An entry in the n-dimensional matrix for the node execution.
-pub(crate) struct MatrixCell {
+pub struct MatrixCell {
     /// Environment variables.
     pub env: Option<HashMap<String, String>>,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(rename_all = "snake_case", deny_unknown_fields)]
-/// An individual task.
-pub(crate) struct Task {
-    /// The script content to execute. Can contain handlebars placeholders.
-    pub script: String,
+/// An individual task. Exactly one of the task kinds (`script`, `node`,
+/// `copy`, `mkdir`, `remove`, `template` or `wait_for`) must be set. All
+/// values can contain handlebars placeholders.
+pub struct Task {
+    /// The script content to execute.
+    pub script: Option<String>,
+    /// Calls another node inline. Its tasks, env vars, shell and workdir are
+    /// used while its matrix and `pre` nodes are ignored.
+    pub node: Option<String>,
+    /// Copies a file or directory (recursively).
+    pub copy: Option<CopyTask>,
+    /// Creates a directory including all of its parents.
+    pub mkdir: Option<String>,
+    /// Removes a file or directory (recursively). Missing paths are ignored.
+    pub remove: Option<String>,
+    /// Renders a handlebars template file to disk.
+    pub template: Option<TemplateTask>,
+    /// Waits until a resource is ready.
+    pub wait_for: Option<WaitForTask>,
 
     /// Explicitly set env vars.
     pub env: Option<HashMap<String, String>>,
@@ -216,30 +453,277 @@ pub(crate) struct Task {
     pub shell: Option<Shell>,
     /// Custom workdir.
     pub workdir: Option<String>,
+    /// Gives the script the terminal as its stdin, stdout and stderr. No other
+    /// invocation runs while the invocation of an interactive script runs.
+    pub interactive: Option<bool>,
+    /// The stdin of the script.
+    pub stdin: Option<Stdin>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(untagged)]
+/// The stdin of a script.
+pub enum Stdin {
+    /// A literal value.
+    Content(String),
+    /// The content of a file.
+    File(StdinFile),
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+/// A file whose content is piped into a script.
+pub struct StdinFile {
+    /// The path, relative to the workdir. Read when the script starts.
+    pub file: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+/// Copies a file or directory.
+pub struct CopyTask {
+    /// The source path.
+    pub from: String,
+    /// The destination path.
+    pub to: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+/// Renders a handlebars template file to disk. The template is read and
+/// rendered with the args during planning.
+pub struct TemplateTask {
+    /// The template file.
+    pub src: String,
+    /// The file to write the rendered template to.
+    pub dst: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+/// Polls a resource until it is ready. Exactly one of `tcp` or `file` must be
+/// set.
+pub struct WaitForTask {
+    /// A TCP address (like "localhost:5432") that must accept connections.
+    pub tcp: Option<String>,
+    /// A path that must exist.
+    pub file: Option<String>,
+    /// Timeout in seconds (default 30).
+    pub timeout: Option<u64>,
+    /// Polling interval in milliseconds (default 500).
+    pub interval: Option<u64>,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(rename_all = "snake_case", deny_unknown_fields)]
 /// Watch definition.
-pub(crate) struct WatchExec {
-    /// Regex filter.
-    pub filter: String,
+pub struct WatchExec {
+    /// Regex filter. Defaults to all created, modified and removed events.
+    pub filter: Option<String>,
     /// Whether to process all messages or skip processing as long as one is
     /// running.
     pub queue: bool,
+    /// Whether to terminate the running execution and start a fresh one on
+    /// every change. Takes precedence over `queue`.
+    pub restart: Option<bool>,
+    /// Whether to execute once at startup before waiting for changes.
+    pub initial_run: Option<bool>,
+    /// Time window in milliseconds. Changes are only processed once no further
+    /// change happened within this window.
+    pub debounce: Option<u64>,
+    /// The maximum time in milliseconds changes are held back by the debounce
+    /// while further changes keep arriving. Defaults to 10 times the debounce.
+    pub debounce_max: Option<u64>,
+    /// Whether to merge all changes (within the debounce window) into a single
+    /// execution. The changed paths are exposed as `EVENT_PATHS` array.
+    pub batch: Option<bool>,
+    /// Gitignore style globs for paths to ignore. Ignored paths are skipped
+    /// before the filter is applied and ignored directories are not watched.
+    pub ignore: Option<Vec<String>>,
+    /// Whether to also ignore the paths that are ignored by the `.gitignore`
+    /// file in the root (and the `.git` directory itself).
+    pub gitignore: Option<bool>,
+    /// The paths to watch, relative to the root. Defaults to the root itself.
+    pub paths: Option<Vec<String>>,
+    /// Whether to derive the watched paths and an additional path filter from
+    /// the `inputs` of the referenced nodes and their transitive `pre` nodes.
+    pub from_inputs: Option<bool>,
+    /// The backend that detects the changes. Defaults to the native one.
+    #[serde(default, with = "serde_yaml::with::singleton_map_recursive")]
+    #[schemars(with = "Option<WatchBackend>")]
+    pub backend: Option<WatchBackend>,
     /// Execution steps.
     #[serde(with = "serde_yaml::with::singleton_map_recursive")]
-    #[schemars(with = "WatchExecStep")]
-    pub exec: WatchExecStep,
+    #[schemars(with = "WatchExecSteps")]
+    pub exec: WatchExecSteps,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+/// Backend that detects filesystem changes.
+pub enum WatchBackend {
+    /// The native notification mechanism of the platform (e.g. inotify).
+    Native,
+    /// Scans the paths periodically. Works on filesystems that do not emit
+    /// native events like bind mounts or NFS.
+    Poll {
+        /// Polling interval in milliseconds.
+        interval: u64,
+        /// Whether to compare file contents (hashes) instead of only the
+        /// modification times to avoid spurious triggers.
+        compare_contents: Option<bool>,
+    },
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(untagged)]
+/// A single execution step or a list of them that are executed together.
+pub enum WatchExecSteps {
+    One(WatchExecStep),
+    Many(Vec<WatchExecStep>),
+}
+
+impl WatchExecSteps {
+    pub fn steps(&self) -> Vec<&WatchExecStep> {
+        match self {
+            | Self::One(v) => vec![v],
+            | Self::Many(v) => v.iter().collect(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(rename_all = "snake_case", deny_unknown_fields)]
 /// Single execution step.
-pub(crate) enum WatchExecStep {
+pub enum WatchExecStep {
     /// Reference and call a node.
     Node {
         #[serde(rename = "ref")]
         ref_: String,
+        /// Values for handlebars placeholders, replacing the ones given via the
+        /// command line.
+        args: Option<BTreeMap<String, String>>,
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_are_merged_below_nodes() {
+        let wf = Workflow::load(
+            r#"
+version: "0.0"
+templates:
+  base:
+    description: base
+    env: { A: "base", B: "base" }
+    pre: [x]
+    inputs: ["src/**"]
+    workdir: ./base
+    lock: db
+    tasks:
+      - script: "echo base"
+  rust:
+    extends: base
+    env: { B: "rust", C: "rust" }
+    pre: [y]
+    tasks:
+      - script: "echo rust"
+nodes:
+  x:
+    tasks: []
+  y:
+    tasks: []
+  build:
+    extends: rust
+    description: build
+    env: { C: "build" }
+    pre: [x]
+    inputs: ["Cargo.toml"]
+    tasks:
+      - script: "echo build"
+"#,
+        )
+        .unwrap();
+        let node = &wf.nodes["build"];
+        assert_eq!(node.extends, None);
+        assert_eq!(node.description.as_deref(), Some("build"));
+        assert_eq!(node.workdir.as_deref(), Some("./base"));
+        assert_eq!(node.lock.as_deref(), Some("db"));
+        assert_eq!(
+            node.env,
+            Some(HashMap::from([
+                ("A".to_owned(), "base".to_owned()),
+                ("B".to_owned(), "rust".to_owned()),
+                ("C".to_owned(), "build".to_owned()),
+            ]))
+        );
+        assert_eq!(
+            node.pre,
+            Some(vec![Pre::Name("x".to_owned()), Pre::Name("y".to_owned())])
+        );
+        assert_eq!(node.inputs, Some(vec!["src/**".to_owned(), "Cargo.toml".to_owned()]));
+        let scripts = node.tasks.iter().map(|v| v.script.clone().unwrap()).collect_vec();
+        assert_eq!(scripts, vec!["echo base", "echo rust", "echo build"]);
+    }
+
+    #[test]
+    fn nodes_can_extend_templates_with_their_own_name() {
+        let wf = Workflow::load(
+            r#"
+version: "0.0"
+templates:
+  build:
+    tasks:
+      - script: "echo template"
+nodes:
+  build:
+    extends: build
+    tasks:
+      - script: "echo node"
+"#,
+        )
+        .unwrap();
+        assert_eq!(wf.nodes["build"].tasks.len(), 2);
+    }
+
+    #[test]
+    fn template_cycles_are_rejected() {
+        let res = Workflow::load(
+            r#"
+version: "0.0"
+templates:
+  a:
+    extends: b
+  b:
+    extends: a
+nodes:
+  n:
+    extends: a
+"#,
+        );
+        match res.unwrap_err().downcast_ref::<Error>() {
+            | Some(Error::TemplateRecursion(v)) => assert_eq!(v, "a -> b -> a"),
+            | v => panic!("unexpected error {:?}", v),
+        }
+    }
+
+    #[test]
+    fn memory_sizes_are_parsed() {
+        assert_eq!(Resources::parse_memory("1024").unwrap(), 1024);
+        assert_eq!(Resources::parse_memory("512K").unwrap(), 512 << 10);
+        assert_eq!(Resources::parse_memory(" 16m ").unwrap(), 16 << 20);
+        assert_eq!(Resources::parse_memory("8G").unwrap(), 8 << 30);
+        assert_eq!(Resources::parse_memory("2T").unwrap(), 2 << 40);
+        assert!(Resources::parse_memory("").is_err());
+        assert!(Resources::parse_memory("G").is_err());
+        assert!(Resources::parse_memory("-1G").is_err());
+        assert!(Resources::parse_memory("1.5G").is_err());
+        match Resources::parse_memory("16777216T").unwrap_err().downcast::<Error>() {
+            | Ok(Error::Argument(v)) => assert_eq!(v, "memory size 16777216T is too large"),
+            | v => panic!("unexpected result {:?}", v),
+        }
+    }
+}
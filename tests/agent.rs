@@ -0,0 +1,111 @@
+use {
+    neomake::{
+        agent::{
+            Agent,
+            RemoteExecutor,
+            MAX_REQUEST,
+        },
+        error::Error,
+        Compiler,
+        ExecutionEngine,
+        Workflow,
+    },
+    std::{
+        collections::{
+            HashMap,
+            HashSet,
+        },
+        io::{
+            BufRead,
+            BufReader,
+            Write,
+        },
+        net::TcpStream,
+        sync::Arc,
+    },
+};
+
+const WORKFLOW: &str = r#"
+version: "0.0"
+nodes:
+  remote:
+    tasks:
+      - script: echo "hello $NAME"
+        env:
+          NAME: agent
+      - script: exit 3
+  mixed:
+    tasks:
+      - mkdir: ./tmp
+      - script: echo mixed
+"#;
+
+/// Starts an agent on a random local port and returns its address.
+fn agent(token: &str) -> String {
+    let agent = Agent::bind("127.0.0.1:0", token).unwrap();
+    let addr = agent.local_addr().unwrap().to_string();
+    std::thread::spawn(move || agent.serve());
+    addr
+}
+
+/// Executes the node on the agent and returns the output lines alongside the
+/// result.
+fn execute(addr: &str, token: &str, node: &str) -> (Vec<String>, anyhow::Result<()>) {
+    let plan = Compiler::new(Workflow::load(WORKFLOW).unwrap())
+        .plan(&HashSet::from([node.to_owned()]), &HashMap::new())
+        .unwrap();
+    let (sink_tx, sink_rx) = flume::unbounded::<String>();
+    let engine = ExecutionEngine::default()
+        .with_sink(sink_tx)
+        .with_executor(
+            "agents",
+            Arc::new(RemoteExecutor::new(vec![addr.to_owned()], token).unwrap()),
+        )
+        .with_default_executor("agents");
+    let res = engine.execute(&plan);
+    drop(engine);
+    (sink_rx.iter().collect(), res)
+}
+
+#[test]
+fn tasks_run_on_the_agent() {
+    let addr = agent("s3cret");
+    let (lines, res) = execute(&addr, "s3cret", "remote");
+    assert_eq!(lines, vec!["hello agent\n".to_owned()]);
+    // the failing task reports its exit code
+    assert!(format!("{:?}", res.unwrap_err()).contains("failed to execute with code 3"));
+}
+
+#[test]
+fn requests_with_a_wrong_token_are_rejected() {
+    let addr = agent("s3cret");
+    let (lines, res) = execute(&addr, "guess", "remote");
+    assert!(lines.is_empty());
+    assert!(format!("{:?}", res.unwrap_err()).contains("unauthorized"));
+}
+
+#[test]
+fn non_script_tasks_are_rejected() {
+    let addr = agent("s3cret");
+    let (_, res) = execute(&addr, "s3cret", "mixed");
+    match res.unwrap_err().downcast::<Error>() {
+        | Ok(Error::InvalidTask(v)) => assert!(v.contains("can only contain script tasks")),
+        | v => panic!("unexpected result {:?}", v),
+    }
+    assert!(!std::path::Path::new("./tmp").exists());
+}
+
+#[test]
+fn agents_require_a_token() {
+    assert!(Agent::bind("127.0.0.1:0", "").is_err());
+}
+
+#[test]
+fn oversized_requests_are_rejected() {
+    let addr = agent("s3cret");
+    let mut stream = TcpStream::connect(&addr).unwrap();
+    stream.write_all(&vec![b'a'; MAX_REQUEST as usize]).unwrap();
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).unwrap();
+    assert_eq!(line, "{\"error\":\"incomplete or oversized request\"}\n");
+}
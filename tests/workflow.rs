@@ -0,0 +1,66 @@
+use std::{
+    io::Write,
+    process::{
+        Command,
+        Output,
+        Stdio,
+    },
+};
+
+/// Plans the nodes of the test workflow and executes the plan.
+fn run(nodes: &[&str], args: &[&str]) -> Output {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/test");
+    let mut plan = Command::new(env!("CARGO_BIN_EXE_neomake"));
+    plan.current_dir(dir).args(["plan", "--workflow", ".neomake.yaml"]);
+    for node in nodes {
+        plan.args(["-n", node]);
+    }
+    for arg in args {
+        plan.args(["-a", arg]);
+    }
+    let plan = plan.output().unwrap();
+    assert!(plan.status.success(), "{}", String::from_utf8_lossy(&plan.stderr));
+
+    let mut execute = Command::new(env!("CARGO_BIN_EXE_neomake"))
+        .current_dir(dir)
+        .args(["execute", "-w", "4"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    execute.stdin.take().unwrap().write_all(&plan.stdout).unwrap();
+    execute.wait_with_output().unwrap()
+}
+
+#[test]
+fn task_kinds_run_in_order() {
+    let out = run(&["india"], &["args.name=india"]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "hello india\nfrom stdin\nalpha");
+    assert!(!std::path::Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test/tmp/india")).exists());
+}
+
+#[test]
+fn predecessors_run_first() {
+    let out = run(&["bravo"], &[]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "alphabravo");
+}
+
+#[test]
+fn env_vars_are_merged() {
+    let out = run(&["charlie"], &[]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "val_global\nval_node\nval_matrix\nval_task\n"
+    );
+}
+
+#[test]
+fn failing_tasks_fail_the_execution() {
+    let out = run(&["golf"], &[]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("failed to execute with code 1"));
+}